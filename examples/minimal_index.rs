@@ -1,14 +1,13 @@
+use bevy::core::Name;
 use bevy::prelude::*;
 use bevy_index::{ComponentIndex, ComponentIndexes};
 
-#[derive(Clone, Hash, PartialEq, Eq)]
-struct Name(&'static str);
 #[derive(Debug)]
 struct Score(isize);
 
 fn main() {
     App::build()
-        .init_index::<Name>()
+        .init_name_index()
         .add_startup_system(create_npcs.system())
         .add_system(get_cart_score.system())
         .run()
@@ -16,22 +15,16 @@ fn main() {
 
 fn create_npcs(commands: &mut Commands) {
     commands
-        .spawn((Name("Alice"), Score(0)))
-        .spawn((Name("Bevy"), Score(1)))
-        .spawn((Name("Cart"), Score(2)));
+        .spawn((Name::new("Alice"), Score(0)))
+        .spawn((Name::new("Bevy"), Score(1)))
+        .spawn((Name::new("Cart"), Score(2)));
 }
 
 fn get_cart_score(query: Query<&Score>, name_index: Res<ComponentIndex<Name>>) {
-    let carts = name_index.get(&Name("Cart"));
-
-    for cart in carts.iter() {
-        // For all components within the query, instead use
-        // let alice_components = query.get(*alice)
-        let cart_score = query.get_component::<Score>(*cart);
-
-        match cart_score {
-            Ok(s) => println!("The entity {:?} named Cart has a score of {:?}.", cart, s.0),
-            Err(_) => println!("Error when attempting to find entity {:?}.", cart),
-        };
+    for (cart, score) in name_index.get_with(&Name::new("Cart"), &query) {
+        println!(
+            "The entity {:?} named Cart has a score of {:?}.",
+            cart, score.0
+        );
     }
 }
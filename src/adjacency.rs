@@ -0,0 +1,148 @@
+use crate::removal::iter_removed;
+use bevy::prelude::*;
+use multimap::MultiMap;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// A directed edge between two entities, stored as a component on its own entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Edge {
+    pub from: Entity,
+    pub to: Entity,
+}
+
+/// An index over `Edge` components, tracking the neighbors of each entity in both directions
+#[derive(Debug, Default)]
+pub struct AdjacencyIndex {
+    outgoing: MultiMap<Entity, Entity>,
+    incoming: MultiMap<Entity, Entity>,
+    // Maps each edge entity to the edge it was indexed as
+    edges: HashMap<Entity, Edge>,
+}
+
+impl AdjacencyIndex {
+    pub fn new() -> Self {
+        AdjacencyIndex::default()
+    }
+
+    /// Entities that `entity` has an edge to, with one entry per edge
+    pub fn get_out(&self, entity: Entity) -> Cow<'_, [Entity]> {
+        match self.outgoing.get_vec(&entity) {
+            Some(e) => Cow::from(e),
+            None => Cow::from(Vec::new()),
+        }
+    }
+
+    /// Entities that have an edge to `entity`, with one entry per edge
+    pub fn get_in(&self, entity: Entity) -> Cow<'_, [Entity]> {
+        match self.incoming.get_vec(&entity) {
+            Some(e) => Cow::from(e),
+            None => Cow::from(Vec::new()),
+        }
+    }
+
+    /// The edge that `edge_entity` was indexed as
+    pub fn edge_of(&self, edge_entity: Entity) -> Option<&Edge> {
+        self.edges.get(&edge_entity)
+    }
+
+    pub(crate) fn insert(&mut self, edge: Edge, edge_entity: Entity) {
+        self.remove(&edge_entity);
+
+        self.outgoing.insert(edge.from, edge.to);
+        self.incoming.insert(edge.to, edge.from);
+        self.edges.insert(edge_entity, edge);
+    }
+
+    pub(crate) fn remove(&mut self, edge_entity: &Entity) {
+        if let Some(edge) = self.edges.remove(edge_entity) {
+            // Parallel edges are stored once each, so only drop a single copy
+            remove_one(&mut self.outgoing, &edge.from, edge.to);
+            remove_one(&mut self.incoming, &edge.to, edge.from);
+        }
+    }
+}
+
+fn remove_one(map: &mut MultiMap<Entity, Entity>, key: &Entity, neighbor: Entity) {
+    if let Some(neighbors) = map.get_vec_mut(key) {
+        if let Some(i) = neighbors.iter().position(|e| *e == neighbor) {
+            neighbors.remove(i);
+        }
+        if neighbors.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+pub(crate) fn update_adjacency_index(
+    mut index: ResMut<AdjacencyIndex>,
+    query: Query<(&Edge, Entity)>,
+    changed_query: Query<(&Edge, Entity), Changed<Edge>>,
+) {
+    // Despawning an edge entity removes it in both directions
+    for entity in iter_removed::<Edge, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (edge, entity) in changed_query.iter() {
+        if index.edge_of(entity) != Some(edge) {
+            index.insert(*edge, entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[test]
+    fn neighbors_test() {
+        let mut index = AdjacencyIndex::new();
+        let (a, b, c) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        let (ab, ac, cb) = (Entity::new(10), Entity::new(11), Entity::new(12));
+        index.insert(Edge { from: a, to: b }, ab);
+        index.insert(Edge { from: a, to: c }, ac);
+        index.insert(Edge { from: c, to: b }, cb);
+
+        assert_eq!(index.get_out(a).as_ref(), &[b, c]);
+        assert_eq!(index.get_in(b).as_ref(), &[a, c]);
+        assert!(index.get_in(a).is_empty());
+        assert!(index.get_out(b).is_empty());
+
+        index.remove(&ab);
+        assert_eq!(index.get_out(a).as_ref(), &[c]);
+        assert_eq!(index.get_in(b).as_ref(), &[c]);
+    }
+
+    #[test]
+    fn adjacency_update_test() {
+        let mut app_builder = App::build();
+        app_builder.init_adjacency_index();
+        let world = &mut app_builder.app.world;
+        let a = world.spawn(());
+        let b = world.spawn(());
+        let c = world.spawn(());
+        let ab = world.spawn((Edge { from: a, to: b },));
+        let bc = world.spawn((Edge { from: b, to: c },));
+        app_builder.app.update();
+
+        {
+            let index = app_builder.resources().get::<AdjacencyIndex>().unwrap();
+            assert_eq!(index.get_out(b).as_ref(), &[c]);
+            assert_eq!(index.get_in(b).as_ref(), &[a]);
+        }
+
+        app_builder.app.world.despawn(ab).unwrap();
+        app_builder.app.world.get_mut::<Edge>(bc).unwrap().to = a;
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<AdjacencyIndex>().unwrap();
+        assert!(index.get_out(a).is_empty());
+        assert!(index.get_in(b).is_empty());
+        assert_eq!(index.get_out(b).as_ref(), &[a]);
+        assert_eq!(index.get_in(a).as_ref(), &[b]);
+        assert!(index.get_in(c).is_empty());
+    }
+}
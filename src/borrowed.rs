@@ -0,0 +1,112 @@
+use crate::removal::iter_removed;
+use crate::{ComponentIndex, IndexKey};
+use bevy::prelude::*;
+
+use std::borrow::Cow;
+
+/// The function used to compute the key of each `C` for the `ComponentIndex<K>` it feeds.
+/// Keys that are already stored in `C` can be borrowed, so that they're only cloned when the index actually changes.
+pub struct BorrowedProjection<C, K: Clone> {
+    pub(crate) project: for<'a> fn(&'a C) -> Cow<'a, K>,
+}
+
+pub(crate) fn update_borrowed_projection_index<C: Component, K: IndexKey>(
+    mut index: ResMut<ComponentIndex<K>>,
+    projection: Res<BorrowedProjection<C, K>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        let key = (projection.project)(component);
+        // Most changes leave the key alone, and comparing a borrowed key doesn't need a clone
+        if !index.is_stored_as(entity, &key) {
+            index.insert(key.into_owned(), entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    #[cfg(test)]
+    use crate::test_utils::assert_indexed;
+    use crate::ComponentIndexes;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    // Counts its clones, to check that unchanged keys are never copied
+    #[derive(Debug, Hash, PartialEq, Eq)]
+    struct Faction(u8);
+
+    impl Clone for Faction {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, Ordering::SeqCst);
+            Faction(self.0)
+        }
+    }
+
+    struct Unit {
+        faction: Faction,
+        // Mercenaries are indexed under a faction derived from their employer instead
+        employer: Option<u8>,
+        health: u8,
+    }
+
+    fn faction_of(unit: &Unit) -> Cow<'_, Faction> {
+        match unit.employer {
+            Some(employer) => Cow::Owned(Faction(employer)),
+            None => Cow::Borrowed(&unit.faction),
+        }
+    }
+
+    #[test]
+    fn borrowed_projection_test() {
+        let mut app_builder = App::build();
+        app_builder.init_borrowed_projection::<Unit, Faction>(faction_of);
+        let world = &mut app_builder.app.world;
+        let soldiers: Vec<Entity> = (0..3)
+            .map(|_| {
+                world.spawn((Unit {
+                    faction: Faction(0),
+                    employer: None,
+                    health: 10,
+                },))
+            })
+            .collect();
+        let mercenary = world.spawn((Unit {
+            faction: Faction(0),
+            employer: Some(1),
+            health: 10,
+        },));
+        app_builder.app.update();
+
+        assert_indexed(&app_builder.app, &Faction(0), &soldiers);
+        assert_indexed(&app_builder.app, &Faction(1), &[mercenary]);
+
+        // Taking damage changes every unit, but none of their keys
+        CLONES.store(0, Ordering::SeqCst);
+        let world = &mut app_builder.app.world;
+        for entity in soldiers.iter().chain(Some(&mercenary)) {
+            world.get_mut::<Unit>(*entity).unwrap().health -= 1;
+        }
+        app_builder.app.update();
+        assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+
+        app_builder
+            .app
+            .world
+            .get_mut::<Unit>(soldiers[0])
+            .unwrap()
+            .employer = Some(1);
+        app_builder.app.update();
+
+        assert_indexed(&app_builder.app, &Faction(0), &soldiers[1..]);
+        assert_indexed(&app_builder.app, &Faction(1), &[mercenary, soldiers[0]]);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::removal::iter_removed;
+use crate::{
+    begin_index_frame, fixed, metrics, tick_ttl_frames, tick_ttl_seconds, update_index,
+    ComponentIndex, ComponentIndexes, ConsistencyPolicy, IndexFilter, IndexKey, IndexMetrics,
+    TtlClock, FIXED_INDEX_UPDATE,
+};
+use bevy::prelude::*;
+
+use std::marker::PhantomData;
+
+/// Configures every option of a `ComponentIndex<T>` in one place, before adding it to the app with `build`.
+/// `ComponentIndexes::init_index::<T>()` is a shortcut for `IndexBuilder::<T>::new().build(app_builder)`.
+pub struct IndexBuilder<T: IndexKey> {
+    stage: &'static str,
+    capacity: usize,
+    register_update: fn(&mut AppBuilder, &'static str, bool),
+    filter: Option<fn(&World, Entity) -> bool>,
+    catch_up_in_last: bool,
+    ttl: Option<(TtlClock, f64)>,
+    drop_empty_keys: bool,
+    consistency_policy: ConsistencyPolicy,
+    track_insertion_order: bool,
+    phantom: PhantomData<T>,
+}
+
+impl<T: IndexKey> IndexBuilder<T> {
+    pub fn new() -> Self {
+        IndexBuilder::<T> {
+            stage: stage::POST_UPDATE,
+            capacity: 0,
+            register_update: register_update::<T>,
+            filter: None,
+            catch_up_in_last: false,
+            ttl: None,
+            drop_empty_keys: false,
+            consistency_policy: ConsistencyPolicy::default(),
+            track_insertion_order: false,
+            phantom: PhantomData,
+        }
+    }
+
+    /// The stage that the index is updated in, `POST_UPDATE` by default
+    pub fn stage(mut self, stage: &'static str) -> Self {
+        self.stage = stage;
+        self
+    }
+
+    /// Updates the index once more in `LAST`, to pick up components inserted by commands
+    /// flushed at the end of `POST_UPDATE`, such as by the scene spawner.
+    /// This doubles the cost of keeping the index up to date, so it's off by default.
+    /// Indexes updated in `LAST` or in the fixed timestep stage never catch up.
+    pub fn catch_up_in_last(mut self) -> Self {
+        self.catch_up_in_last = true;
+        self
+    }
+
+    /// Reserves room for `capacity` entities up front
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Only indexes entities that also have a `C`
+    pub fn filter<C: Component>(mut self) -> Self {
+        self.register_update = register_filtered_update::<T, C>;
+        self.filter = Some(<With<C> as IndexFilter>::matches);
+        self
+    }
+
+    /// Only indexes entities that match the query filter `F`, as in `ComponentIndexes::init_index_with_filter`
+    pub fn query_filter<F: IndexFilter>(mut self) -> Self {
+        self.register_update = register_query_filtered_update::<T, F>;
+        self.filter = Some(F::matches);
+        self
+    }
+
+    /// Evicts entries after `ttl` units of `clock`, as in `ComponentIndexes::init_index_with_ttl`
+    pub fn ttl(mut self, clock: TtlClock, ttl: f64) -> Self {
+        self.ttl = Some((clock, ttl));
+        self
+    }
+
+    /// Drops keys as soon as their last entity leaves, see `ComponentIndex::set_drop_empty_keys`
+    pub fn drop_empty_keys(mut self) -> Self {
+        self.drop_empty_keys = true;
+        self
+    }
+
+    pub fn consistency_policy(mut self, policy: ConsistencyPolicy) -> Self {
+        self.consistency_policy = policy;
+        self
+    }
+
+    /// Tracks the order entities are first indexed in, see `ComponentIndex::iter_in_insertion_order`
+    pub fn track_insertion_order(mut self) -> Self {
+        self.track_insertion_order = true;
+        self
+    }
+
+    /// Adds the configured index and its systems to the app
+    pub fn build(self, app_builder: &mut AppBuilder) {
+        let mut index = ComponentIndex::<T>::with_capacity(self.capacity);
+        index.default_ttl = self.ttl.map(|(_, ttl)| ttl);
+        index.set_drop_empty_keys(self.drop_empty_keys);
+        index.set_consistency_policy(self.consistency_policy);
+        index.set_track_insertion_order(self.track_insertion_order);
+        index.filter = self.filter;
+
+        app_builder.add_resource(index);
+        app_builder.init_resource::<IndexMetrics<T>>();
+        app_builder.add_system_to_stage(stage::FIRST, metrics::reset_index_metrics::<T>.system());
+        app_builder.add_system_to_stage(stage::FIRST, begin_index_frame::<T>.system());
+        let catch_up = self.catch_up_in_last && can_catch_up_in_last(self.stage);
+        // Added first, so that it runs before the update system it shares the index with
+        if self.stage == FIXED_INDEX_UPDATE {
+            app_builder.add_system_to_stage(self.stage, fixed::resync_on_tick::<T>.system());
+        }
+        (self.register_update)(app_builder, self.stage, catch_up);
+
+        match self.ttl {
+            Some((TtlClock::Frames, _)) => {
+                app_builder.add_system_to_stage(self.stage, tick_ttl_frames::<T>.system());
+            }
+            Some((TtlClock::Seconds, _)) => {
+                app_builder.add_system_to_stage(self.stage, tick_ttl_seconds::<T>.system());
+            }
+            None => (),
+        }
+    }
+}
+
+impl<T: IndexKey> Default for IndexBuilder<T> {
+    fn default() -> Self {
+        IndexBuilder::<T>::new()
+    }
+}
+
+fn register_update<T: IndexKey>(app_builder: &mut AppBuilder, stage: &'static str, catch_up: bool) {
+    // FIXME: this should instead be run automatically whenever an index is used
+    // Otherwise there's no guarantee it's fresh
+    app_builder.add_startup_system_to_stage(
+        "post_startup",
+        AppBuilder::update_component_index::<T>.system(),
+    );
+    app_builder.add_system_to_stage(stage, AppBuilder::update_component_index::<T>.system());
+    // Catches components inserted by commands flushed at the end of POST_UPDATE,
+    // which would otherwise have their change flags cleared before we could see them
+    if catch_up {
+        app_builder.add_system_to_stage(
+            stage::LAST,
+            AppBuilder::update_component_index::<T>.system(),
+        );
+    }
+}
+
+// Catching up every frame would defeat a stage that deliberately only runs on some frames
+fn can_catch_up_in_last(stage: &'static str) -> bool {
+    stage != stage::LAST && stage != FIXED_INDEX_UPDATE
+}
+
+fn register_filtered_update<T: IndexKey, C: Component>(
+    app_builder: &mut AppBuilder,
+    stage: &'static str,
+    catch_up: bool,
+) {
+    app_builder.add_startup_system_to_stage("post_startup", update_filtered_index::<T, C>.system());
+    app_builder.add_system_to_stage(stage, update_filtered_index::<T, C>.system());
+    if catch_up {
+        app_builder.add_system_to_stage(stage::LAST, update_filtered_index::<T, C>.system());
+    }
+}
+
+fn update_filtered_index<T: IndexKey, C: Component>(
+    mut index: ResMut<ComponentIndex<T>>,
+    mut metrics: ResMut<IndexMetrics<T>>,
+    query: Query<(&T, Entity), With<C>>,
+    changed_query: Query<(&T, Entity), (With<C>, Or<(Changed<T>, Added<C>)>)>,
+) {
+    // Losing either the key or the filter component evicts the entity
+    update_index(
+        &mut index,
+        &mut metrics,
+        || query.iter(),
+        iter_removed::<T, _, _>(&query).chain(iter_removed::<C, _, _>(&query)),
+        changed_query.iter(),
+    );
+}
+
+fn register_query_filtered_update<T: IndexKey, F: IndexFilter>(
+    app_builder: &mut AppBuilder,
+    stage: &'static str,
+    catch_up: bool,
+) {
+    app_builder
+        .add_startup_system_to_stage("post_startup", update_query_filtered_index::<T, F>.system());
+    app_builder.add_system_to_stage(stage, update_query_filtered_index::<T, F>.system());
+    if catch_up {
+        app_builder.add_system_to_stage(stage::LAST, update_query_filtered_index::<T, F>.system());
+    }
+}
+
+fn update_query_filtered_index<T: IndexKey, F: IndexFilter>(
+    mut index: ResMut<ComponentIndex<T>>,
+    mut metrics: ResMut<IndexMetrics<T>>,
+    query: Query<(&T, Entity), F>,
+    changed_query: Query<(&T, Entity), (F, Changed<T>)>,
+    gained_query: Query<Entity, F::Gained>,
+) {
+    // Only entities that gained or lost one of the filter's components can have started or stopped matching it
+    let mut touched: Vec<Entity> = gained_query.iter().collect();
+    F::extend_removed(&query, &mut touched);
+    touched.extend(iter_removed::<T, _, _>(&query));
+
+    let dropped: Vec<Entity> = touched
+        .iter()
+        .copied()
+        .filter(|entity| query.get(*entity).is_err())
+        .collect();
+    let joined = touched.iter().filter_map(|entity| query.get(*entity).ok());
+
+    // Entities that kept matching only need their key updated when it changed
+    update_index(
+        &mut index,
+        &mut metrics,
+        || query.iter(),
+        dropped,
+        joined.chain(changed_query.iter()),
+    );
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    #[cfg(test)]
+    use crate::test_utils::assert_indexed;
+    use crate::Lookup;
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct Room(u8);
+
+    struct Awake;
+    struct Pinned;
+    struct Hidden;
+
+    // Records what the index holds for Room(0) as seen from UPDATE
+    fn record_room(index: Res<ComponentIndex<Room>>, mut seen: ResMut<Vec<Entity>>) {
+        *seen = index.get(&Room(0)).to_vec();
+    }
+
+    #[test]
+    fn builder_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .add_resource(Vec::<Entity>::new())
+            .add_system(record_room.system());
+        IndexBuilder::<Room>::new()
+            .stage(stage::PRE_UPDATE)
+            .capacity(64)
+            .filter::<Awake>()
+            .drop_empty_keys()
+            .consistency_policy(ConsistencyPolicy::Panic)
+            .build(&mut app_builder);
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<Room>>()
+                .unwrap();
+            assert!(index.reverse().capacity() >= 64);
+            assert_eq!(index.consistency_policy, ConsistencyPolicy::Panic);
+        }
+
+        let world = &mut app_builder.app.world;
+        let awake = world.spawn((Room(0), Awake));
+        let asleep = world.spawn((Room(0),));
+        app_builder.app.update();
+
+        // Updated in PRE_UPDATE, so systems in UPDATE already see this frame's changes
+        assert_eq!(
+            *app_builder.resources().get::<Vec<Entity>>().unwrap(),
+            vec![awake]
+        );
+
+        let world = &mut app_builder.app.world;
+        *world.get_mut::<Room>(awake).unwrap() = Room(1);
+        world.insert_one(asleep, Awake).unwrap();
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<Room>>()
+                .unwrap();
+            assert_eq!(index.get(&Room(0)).as_ref(), &[asleep]);
+            assert_eq!(index.get(&Room(1)).as_ref(), &[awake]);
+        }
+
+        app_builder.app.world.remove_one::<Awake>(asleep).unwrap();
+        app_builder.app.update();
+
+        // The emptied key was dropped rather than kept around
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Room>>()
+            .unwrap();
+        assert_eq!(index.lookup(&Room(0)), Lookup::Missing);
+    }
+
+    #[test]
+    fn query_filter_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index_with_filter::<Room, (Or<(With<Awake>, With<Pinned>)>, Without<Hidden>)>();
+        let world = &mut app_builder.app.world;
+        let awake = world.spawn((Room(0), Awake));
+        let pinned = world.spawn((Room(0), Pinned));
+        let hidden = world.spawn((Room(0), Awake, Hidden));
+        let neither = world.spawn((Room(0),));
+        app_builder.app.update();
+
+        assert_indexed(&app_builder.app, &Room(0), &[awake, pinned]);
+
+        // Entities move in and out of the filter without their key changing
+        let world = &mut app_builder.app.world;
+        world.insert_one(awake, Hidden).unwrap();
+        world.remove_one::<Hidden>(hidden).unwrap();
+        world.insert_one(neither, Pinned).unwrap();
+        app_builder.app.update();
+
+        assert_indexed(&app_builder.app, &Room(0), &[pinned, hidden, neither]);
+
+        *app_builder.app.world.get_mut::<Room>(pinned).unwrap() = Room(1);
+        *app_builder.app.world.get_mut::<Room>(awake).unwrap() = Room(1);
+        app_builder.app.update();
+
+        assert_indexed(&app_builder.app, &Room(0), &[hidden, neither]);
+        assert_indexed(&app_builder.app, &Room(1), &[pinned]);
+    }
+
+    #[test]
+    fn insertion_order_test() {
+        let mut app_builder = App::build();
+        IndexBuilder::<Room>::new()
+            .track_insertion_order()
+            .build(&mut app_builder);
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((Room(1),));
+        let b = world.spawn((Room(2),));
+        let c = world.spawn((Room(1),));
+        app_builder.app.update();
+
+        // Moving keeps an entity's place, while leaving and coming back sends it to the back
+        let world = &mut app_builder.app.world;
+        let d = world.spawn((Room(2),));
+        *world.get_mut::<Room>(a).unwrap() = Room(3);
+        world.remove_one::<Room>(b).unwrap();
+        app_builder.app.update();
+        app_builder.app.world.insert_one(b, Room(1)).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Room>>()
+            .unwrap();
+        let order: Vec<Entity> = index.iter_in_insertion_order().collect();
+        assert_eq!(order, vec![a, c, d, b]);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::ComponentIndex;
+use bevy::prelude::*;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A read-only view of a `ComponentIndex<T>` that remembers the entities of each key it has looked up,
+/// for systems that look up the same keys over and over, such as when visiting neighbors.
+/// The cache lives as long as the view, which borrows the index, so it can never go stale.
+pub struct CachedIndex<'a, T: Hash + Eq> {
+    index: &'a ComponentIndex<T>,
+    cache: RefCell<HashMap<T, &'a [Entity]>>,
+}
+
+impl<'a, T: Hash + Eq + Clone> CachedIndex<'a, T> {
+    pub(crate) fn new(index: &'a ComponentIndex<T>) -> Self {
+        CachedIndex {
+            index,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, component_val: &T) -> &'a [Entity] {
+        if let Some(&entities) = self.cache.borrow().get(component_val) {
+            return entities;
+        }
+
+        let index = self.index;
+        let entities = index.entities_of(component_val).map_or(&[][..], |e| &e[..]);
+        self.cache
+            .borrow_mut()
+            .insert(component_val.clone(), entities);
+        entities
+    }
+
+    /// The number of distinct keys looked up so far
+    pub fn cached_len(&self) -> usize {
+        self.cache.borrow().len()
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cached_index_test() {
+        let mut index = ComponentIndex::<u8>::new();
+        for i in 0..10 {
+            index.insert(i % 3, Entity::new(i as u32));
+        }
+
+        {
+            let cached = index.cached();
+            for _ in 0..5 {
+                for key in 0..4 {
+                    assert_eq!(cached.get(&key), index.get(&key).as_ref());
+                }
+            }
+            assert_eq!(cached.cached_len(), 4);
+        }
+
+        // The index can only change once the view, and its cache, have been dropped
+        index.insert(0, Entity::new(1));
+        let cached = index.cached();
+        assert_eq!(cached.cached_len(), 0);
+        assert_eq!(cached.get(&1), index.get(&1).as_ref());
+        assert!(cached.get(&0).contains(&Entity::new(1)));
+    }
+}
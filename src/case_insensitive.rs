@@ -0,0 +1,82 @@
+use crate::{ComponentIndex, IndexEquiv, LookupKey};
+
+use std::borrow::Borrow;
+
+/// A string key that compares equal to any other string differing only by case,
+/// for use with `ComponentIndexes::init_case_insensitive_index`.
+/// Case is folded with `str::to_lowercase`, so locale-specific rules such as the Turkish dotless i aren't applied.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct CaseInsensitiveString(pub String);
+
+impl IndexEquiv for CaseInsensitiveString {
+    fn canonical(&self) -> Self {
+        CaseInsensitiveString(self.0.to_lowercase())
+    }
+}
+
+// Lets the index be queried with a plain `&str`, which is folded before the lookup
+impl Borrow<str> for CaseInsensitiveString {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl LookupKey<CaseInsensitiveString> for str {
+    fn canonical_key(
+        &self,
+        canonicalize: fn(&CaseInsensitiveString) -> CaseInsensitiveString,
+    ) -> CaseInsensitiveString {
+        canonicalize(&CaseInsensitiveString::from(self))
+    }
+}
+
+impl From<String> for CaseInsensitiveString {
+    fn from(string: String) -> Self {
+        CaseInsensitiveString(string)
+    }
+}
+
+impl From<&str> for CaseInsensitiveString {
+    fn from(string: &str) -> Self {
+        CaseInsensitiveString(string.to_string())
+    }
+}
+
+/// An index over `CaseInsensitiveString` keys, where `get("player")` and `get("Player")` find the same entities
+pub type CaseInsensitiveStringIndex = ComponentIndex<CaseInsensitiveString>;
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+    use bevy::prelude::*;
+
+    #[test]
+    fn case_insensitive_test() {
+        let mut app_builder = App::build();
+        app_builder.init_case_insensitive_index();
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((CaseInsensitiveString::from("Player"),));
+        let b = world.spawn((CaseInsensitiveString::from("pLAYER"),));
+        let c = world.spawn((CaseInsensitiveString::from("Ärger"),));
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<CaseInsensitiveStringIndex>()
+            .unwrap();
+        assert_eq!(index.get("player").as_ref(), &[a, b]);
+        assert_eq!(index.get("PLAYER").as_ref(), &[a, b]);
+        assert_eq!(
+            index.get(&CaseInsensitiveString::from("Player")).as_ref(),
+            &[a, b]
+        );
+        // Non-ASCII letters are folded too
+        assert_eq!(index.get("äRGER").as_ref(), &[c]);
+        assert!(index.get("players").is_empty());
+        assert_eq!(
+            index.value_of(b),
+            Some(&CaseInsensitiveString::from("player"))
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use crate::removal::iter_removed;
+use crate::{ComponentIndex, IndexKey};
+use bevy::prelude::*;
+
+/// The function used to categorize each `C` for the `ComponentIndex<K>` it feeds
+pub struct CategoryProjection<C, K> {
+    pub(crate) categorize: fn(&C) -> K,
+}
+
+pub(crate) fn update_category_index<C: Component, K: IndexKey>(
+    mut index: ResMut<ComponentIndex<K>>,
+    projection: Res<CategoryProjection<C, K>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        let category = (projection.categorize)(component);
+        if !index.is_stored_as(entity, &category) {
+            index.insert(category, entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    enum Category {
+        Weapon,
+        Armor,
+    }
+
+    trait Categorized {
+        fn category(&self) -> Category;
+    }
+
+    struct Sword;
+    struct Bow;
+    struct Shield {
+        bashable: bool,
+    }
+
+    impl Categorized for Sword {
+        fn category(&self) -> Category {
+            Category::Weapon
+        }
+    }
+
+    impl Categorized for Bow {
+        fn category(&self) -> Category {
+            Category::Weapon
+        }
+    }
+
+    impl Categorized for Shield {
+        fn category(&self) -> Category {
+            if self.bashable {
+                Category::Weapon
+            } else {
+                Category::Armor
+            }
+        }
+    }
+
+    #[test]
+    fn category_index_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index_category::<Sword, Category>(|c| c.category())
+            .init_index_category::<Bow, Category>(|c| c.category())
+            .init_index_category::<Shield, Category>(|c| c.category());
+        let world = &mut app_builder.app.world;
+        let sword = world.spawn((Sword,));
+        let bow = world.spawn((Bow,));
+        let shield = world.spawn((Shield { bashable: false },));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<Category>>()
+                .unwrap();
+            let mut weapons = index.get(&Category::Weapon).to_vec();
+            weapons.sort();
+            assert_eq!(weapons, vec![sword, bow]);
+            assert_eq!(index.get(&Category::Armor).as_ref(), &[shield]);
+        }
+
+        let world = &mut app_builder.app.world;
+        world.get_mut::<Shield>(shield).unwrap().bashable = true;
+        world.remove_one::<Bow>(bow).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Category>>()
+            .unwrap();
+        let mut weapons = index.get(&Category::Weapon).to_vec();
+        weapons.sort();
+        assert_eq!(weapons, vec![sword, shield]);
+        assert!(index.get(&Category::Armor).is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::{ComponentIndex, IndexKey, IndexOp};
+use bevy::ecs::ShouldRun;
+use bevy::prelude::*;
+
+use std::collections::HashSet;
+use std::marker::PhantomData;
+
+/// How the changes made to a `ComponentIndex<T>` each frame are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeEventMode {
+    /// One `IndexChanged<T>` event for every entity that was added, reassigned or evicted
+    Granular,
+    /// A single `IndexBatchChanged<T>` event per frame, summarizing every change
+    Batched,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexChange {
+    Added,
+    Reassigned,
+    Evicted,
+}
+
+/// Sent for each entity whose entry in the `ComponentIndex<T>` changed this frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexChanged<T> {
+    pub entity: Entity,
+    pub change: IndexChange,
+    phantom: PhantomData<T>,
+}
+
+/// Sent once per frame, listing every change made to the `ComponentIndex<T>` during that frame.
+/// An entity that was both added and evicted in the same frame appears in both lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexBatchChanged<T> {
+    pub added: Vec<Entity>,
+    pub reassigned: Vec<Entity>,
+    pub evicted: Vec<Entity>,
+    /// Keys that gained their first entity. The first batch also lists the keys occupied at startup.
+    pub occupied: Vec<T>,
+    /// Keys that lost their last entity
+    pub vacated: Vec<T>,
+}
+
+pub(crate) fn send_granular_changes<T: IndexKey>(
+    index: Res<ComponentIndex<T>>,
+    mut events: ResMut<Events<IndexChanged<T>>>,
+) {
+    let changes = [
+        (index.added_this_frame(), IndexChange::Added),
+        (index.reassigned_this_frame(), IndexChange::Reassigned),
+        (index.evicted_this_frame(), IndexChange::Evicted),
+    ];
+
+    for (entities, change) in changes.iter() {
+        for entity in entities.iter() {
+            events.send(IndexChanged {
+                entity: *entity,
+                change: *change,
+                phantom: PhantomData,
+            });
+        }
+    }
+}
+
+pub(crate) fn send_batched_changes<T: IndexKey>(
+    index: Res<ComponentIndex<T>>,
+    mut occupied_keys: Local<OccupiedKeys<T>>,
+    mut events: ResMut<Events<IndexBatchChanged<T>>>,
+) {
+    let (occupied, vacated) = occupied_keys.update(&index);
+    if !index.changed_this_frame() && occupied.is_empty() {
+        return;
+    }
+
+    events.send(IndexBatchChanged {
+        added: index.added_this_frame().to_vec(),
+        reassigned: index.reassigned_this_frame().to_vec(),
+        evicted: index.evicted_this_frame().to_vec(),
+        occupied,
+        vacated,
+    });
+}
+
+/// Sent when `key` gains its first entity or loses its last one, such as a tile becoming occupied or vacated.
+/// Only the state at the end of each frame counts, so a key that is filled and emptied within one frame sends nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOccupancyChanged<T> {
+    pub key: T,
+    pub occupied: bool,
+}
+
+/// The keys that were occupied the last time they were checked, for finding the keys whose occupancy changed since
+pub(crate) struct OccupiedKeys<T> {
+    keys: HashSet<T>,
+    seeded: bool,
+}
+
+impl<T> Default for OccupiedKeys<T> {
+    fn default() -> Self {
+        OccupiedKeys {
+            keys: HashSet::new(),
+            seeded: false,
+        }
+    }
+}
+
+impl<T: IndexKey> OccupiedKeys<T> {
+    // Returns the keys that gained their first entity and the keys that lost their last one since the last check
+    fn update(&mut self, index: &ComponentIndex<T>) -> (Vec<T>, Vec<T>) {
+        let mut occupied = Vec::new();
+        if !self.seeded {
+            // Entities indexed at startup are cleared from the per-frame changes before this first runs,
+            // so the keys they occupy are read off the index itself
+            self.seeded = true;
+            for (_, key) in index.iter() {
+                if self.keys.insert(key.clone()) {
+                    occupied.push(key.clone());
+                }
+            }
+        } else if !index.changed_this_frame() {
+            return (occupied, Vec::new());
+        }
+
+        let vacated: Vec<T> = self
+            .keys
+            .iter()
+            .filter(|key| index.entities_of(*key).map_or(true, |e| e.is_empty()))
+            .cloned()
+            .collect();
+        for key in vacated.iter() {
+            self.keys.remove(key);
+        }
+
+        // Only the keys of entities that were added or moved this frame can have gained their first entity
+        for entity in index
+            .added_this_frame()
+            .iter()
+            .chain(index.reassigned_this_frame())
+        {
+            if let Some(key) = index.value_of(*entity) {
+                if self.keys.insert(key.clone()) {
+                    occupied.push(key.clone());
+                }
+            }
+        }
+        (occupied, vacated)
+    }
+}
+
+pub(crate) fn send_occupancy_changes<T: IndexKey>(
+    index: Res<ComponentIndex<T>>,
+    mut occupied_keys: Local<OccupiedKeys<T>>,
+    mut events: ResMut<Events<KeyOccupancyChanged<T>>>,
+) {
+    let (occupied, vacated) = occupied_keys.update(&index);
+    for key in vacated {
+        events.send(KeyOccupancyChanged {
+            key,
+            occupied: false,
+        });
+    }
+    for key in occupied {
+        events.send(KeyOccupancyChanged {
+            key,
+            occupied: true,
+        });
+    }
+}
+
+/// The keys of a `ComponentIndex<T>` that gained their first entity or lost their last one this frame,
+/// read by the systems added with `ComponentIndexes::add_index_watcher`.
+/// They are gathered from the `IndexBatchChanged<T>` events of the index, and each key is listed once.
+pub struct WatchedKeys<T> {
+    keys: Vec<T>,
+}
+
+impl<T> WatchedKeys<T> {
+    pub fn keys(&self) -> &[T] {
+        &self.keys
+    }
+}
+
+impl<T> Default for WatchedKeys<T> {
+    fn default() -> Self {
+        WatchedKeys { keys: Vec::new() }
+    }
+}
+
+pub(crate) fn collect_watched_keys<T: IndexKey>(
+    mut batch_reader: Local<EventReader<IndexBatchChanged<T>>>,
+    batches: Res<Events<IndexBatchChanged<T>>>,
+    mut watched: ResMut<WatchedKeys<T>>,
+) {
+    watched.keys.clear();
+    let mut affected = HashSet::new();
+    for batch in batch_reader.iter(&batches) {
+        for key in batch.vacated.iter().chain(batch.occupied.iter()) {
+            if affected.insert(key) {
+                watched.keys.push(key.clone());
+            }
+        }
+    }
+}
+
+/// Only runs the index watchers of `T` on frames where at least one of its keys was affected
+pub(crate) fn keys_watched<T: IndexKey>(watched: Res<WatchedKeys<T>>) -> ShouldRun {
+    if watched.keys.is_empty() {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+/// A callback that is pushed every operation applied to a `ComponentIndex<T>`,
+/// for mirroring the index into an external structure
+pub type IndexSink<T> = Box<dyn FnMut(&IndexOp<T>) + Send + Sync>;
+
+/// The sinks registered for a `ComponentIndex<T>` with `ComponentIndexes::add_index_sink`
+pub(crate) struct IndexSinks<T> {
+    pub(crate) sinks: Vec<IndexSink<T>>,
+}
+
+pub(crate) fn feed_index_sinks<T: IndexKey>(
+    mut index: ResMut<ComponentIndex<T>>,
+    mut sinks: ResMut<IndexSinks<T>>,
+) {
+    for op in index.take_log().iter() {
+        for sink in sinks.sinks.iter_mut() {
+            sink(op);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct Cell(bool);
+
+    #[test]
+    fn batched_changes_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index::<Cell>()
+            .init_index_change_events::<Cell>(ChangeEventMode::Batched);
+        let entities: Vec<Entity> = (0..100)
+            .map(|_| app_builder.app.world.spawn((Cell(false),)))
+            .collect();
+        app_builder.app.update();
+
+        let world = &mut app_builder.app.world;
+        for entity in entities[..50].iter() {
+            world.get_mut::<Cell>(*entity).unwrap().0 = true;
+        }
+        for entity in entities[50..60].iter() {
+            world.despawn(*entity).unwrap();
+        }
+        let newborn = world.spawn((Cell(true),));
+        app_builder.app.update();
+
+        let events = app_builder
+            .resources()
+            .get::<Events<IndexBatchChanged<Cell>>>()
+            .unwrap();
+        let mut reader = events.get_reader();
+        let batches: Vec<&IndexBatchChanged<Cell>> = reader.iter(&events).collect();
+        // Startup already indexed every cell, so the first batch only lists the key they occupy
+        assert_eq!(batches.len(), 2);
+        assert!(batches[0].added.is_empty());
+        assert_eq!(batches[0].occupied, vec![Cell(false)]);
+        assert_eq!(batches[1].added, vec![newborn]);
+        assert_eq!(batches[1].reassigned, entities[..50].to_vec());
+        assert_eq!(batches[1].evicted, entities[50..60].to_vec());
+        // Some cells are still off, so only the other key changed occupancy
+        assert_eq!(batches[1].occupied, vec![Cell(true)]);
+        assert!(batches[1].vacated.is_empty());
+    }
+
+    #[test]
+    fn granular_changes_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index::<Cell>()
+            .init_index_change_events::<Cell>(ChangeEventMode::Granular);
+        let cell = app_builder.app.world.spawn((Cell(false),));
+        app_builder.app.update();
+
+        *app_builder.app.world.get_mut::<Cell>(cell).unwrap() = Cell(true);
+        app_builder.app.update();
+
+        let events = app_builder
+            .resources()
+            .get::<Events<IndexChanged<Cell>>>()
+            .unwrap();
+        let mut reader = events.get_reader();
+        let changes: Vec<IndexChange> = reader.iter(&events).map(|e| e.change).collect();
+        assert_eq!(changes, vec![IndexChange::Reassigned]);
+    }
+
+    #[test]
+    fn index_sink_test() {
+        use std::sync::{Arc, Mutex};
+
+        let mirrored: Arc<Mutex<Vec<IndexOp<Cell>>>> = Arc::default();
+        let sink_ops = mirrored.clone();
+        let mut app_builder = App::build();
+        app_builder
+            .init_index::<Cell>()
+            .add_index_sink::<Cell>(Box::new(move |op: &IndexOp<Cell>| {
+                sink_ops.lock().unwrap().push(op.clone())
+            }));
+        let cell = app_builder.app.world.spawn((Cell(false),));
+        app_builder.app.update();
+
+        *app_builder.app.world.get_mut::<Cell>(cell).unwrap() = Cell(true);
+        app_builder.app.update();
+        app_builder.app.world.despawn(cell).unwrap();
+        app_builder.app.update();
+
+        assert_eq!(
+            *mirrored.lock().unwrap(),
+            vec![
+                IndexOp::Insert {
+                    entity: cell,
+                    key: Cell(false)
+                },
+                IndexOp::Reassign {
+                    entity: cell,
+                    from: Cell(false),
+                    to: Cell(true)
+                },
+                IndexOp::Remove { entity: cell },
+            ]
+        );
+    }
+
+    #[test]
+    fn occupancy_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index::<Cell>()
+            .init_key_occupancy_events::<Cell>();
+        let mut reader = EventReader::<KeyOccupancyChanged<Cell>>::default();
+        let mut drain = |app_builder: &AppBuilder| -> Vec<(Cell, bool)> {
+            let events = app_builder
+                .resources()
+                .get::<Events<KeyOccupancyChanged<Cell>>>()
+                .unwrap();
+            reader.iter(&events).map(|e| (e.key, e.occupied)).collect()
+        };
+
+        // Indexed at startup, so it never shows up in the per-frame changes
+        let first = app_builder.app.world.spawn((Cell(true),));
+        app_builder.app.update();
+        assert_eq!(drain(&app_builder), vec![(Cell(true), true)]);
+
+        let second = app_builder.app.world.spawn((Cell(true),));
+        app_builder.app.update();
+        assert!(drain(&app_builder).is_empty());
+
+        // First occupied at runtime
+        let third = app_builder.app.world.spawn((Cell(false),));
+        app_builder.app.update();
+        assert_eq!(drain(&app_builder), vec![(Cell(false), true)]);
+
+        app_builder.app.world.despawn(first).unwrap();
+        app_builder.app.update();
+        assert!(drain(&app_builder).is_empty());
+
+        *app_builder.app.world.get_mut::<Cell>(second).unwrap() = Cell(false);
+        app_builder.app.update();
+        assert_eq!(drain(&app_builder), vec![(Cell(true), false)]);
+
+        app_builder.app.world.despawn(second).unwrap();
+        app_builder.app.world.despawn(third).unwrap();
+        app_builder.app.update();
+        assert_eq!(drain(&app_builder), vec![(Cell(false), false)]);
+    }
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+    struct Tile(u8);
+
+    #[derive(Default)]
+    struct Redraws(Vec<Vec<Tile>>);
+
+    fn redraw_tiles(watched: Res<WatchedKeys<Tile>>, mut redraws: ResMut<Redraws>) {
+        let mut tiles = watched.keys().to_vec();
+        tiles.sort();
+        redraws.0.push(tiles);
+    }
+
+    #[test]
+    fn index_watcher_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_resource::<Redraws>()
+            .init_index::<Tile>()
+            .add_index_watcher::<Tile, _>(redraw_tiles.system());
+        let world = &mut app_builder.app.world;
+        let movers: Vec<Entity> = (0..2).map(|_| world.spawn((Tile(0),))).collect();
+        let doomed = world.spawn((Tile(1),));
+        let wanderer = world.spawn((Tile(2),));
+        world.spawn((Tile(2),));
+        app_builder.app.update();
+
+        for mover in movers.iter() {
+            *app_builder.app.world.get_mut::<Tile>(*mover).unwrap() = Tile(3);
+        }
+        app_builder.app.world.despawn(doomed).unwrap();
+        app_builder.app.update();
+
+        // Both keys stay occupied, so the watcher doesn't run at all
+        *app_builder.app.world.get_mut::<Tile>(wanderer).unwrap() = Tile(3);
+        app_builder.app.update();
+
+        let redraws = app_builder.resources().get::<Redraws>().unwrap();
+        assert_eq!(
+            redraws.0,
+            vec![
+                vec![Tile(0), Tile(1), Tile(2)],
+                vec![Tile(0), Tile(1), Tile(3)],
+            ]
+        );
+    }
+}
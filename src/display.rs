@@ -0,0 +1,105 @@
+use crate::removal::iter_removed;
+use crate::ComponentIndex;
+use bevy::prelude::*;
+
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::marker::PhantomData;
+
+/// An index over the `Display` output of the component `C`, for looking up entities by a human-readable string,
+/// such as from a debug console. Each entity's string is cached, and only formatted again when `C` changes.
+#[derive(Debug)]
+pub struct DisplayIndex<C> {
+    index: ComponentIndex<String>,
+    phantom: PhantomData<C>,
+}
+
+impl<C> DisplayIndex<C> {
+    pub fn new() -> Self {
+        DisplayIndex::<C>::default()
+    }
+
+    /// All entities whose `C` is displayed as exactly `text`
+    pub fn get(&self, text: &str) -> Cow<'_, [Entity]> {
+        self.index.get(text)
+    }
+
+    /// The cached display string of `entity`'s `C`
+    pub fn text_of(&self, entity: Entity) -> Option<&str> {
+        self.index.value_of(entity).map(String::as_str)
+    }
+}
+
+impl<C> Default for DisplayIndex<C> {
+    fn default() -> Self {
+        DisplayIndex::<C> {
+            index: ComponentIndex::new(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+pub(crate) fn update_display_index<C: Component + Display>(
+    mut index: ResMut<DisplayIndex<C>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        let text = component.to_string();
+        if !index.index.is_stored_as(entity, &text) {
+            index.index.insert(text, entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    use std::fmt;
+
+    struct Level(u8);
+
+    impl Display for Level {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Level {}", self.0)
+        }
+    }
+
+    #[test]
+    fn display_index_test() {
+        let mut app_builder = App::build();
+        app_builder.init_display_index::<Level>();
+        let world = &mut app_builder.app.world;
+        let rookie = world.spawn((Level(1),));
+        let veteran = world.spawn((Level(9),));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<DisplayIndex<Level>>()
+                .unwrap();
+            assert_eq!(index.get("Level 1").as_ref(), &[rookie]);
+            assert_eq!(index.text_of(veteran), Some("Level 9"));
+            assert!(index.get("1").is_empty());
+        }
+
+        app_builder.app.world.get_mut::<Level>(rookie).unwrap().0 = 9;
+        app_builder.app.world.remove_one::<Level>(veteran).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<DisplayIndex<Level>>()
+            .unwrap();
+        assert!(index.get("Level 1").is_empty());
+        assert_eq!(index.get("Level 9").as_ref(), &[rookie]);
+        assert_eq!(index.text_of(veteran), None);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{ComponentIndex, IndexKey, IndexMetrics};
+use bevy::prelude::*;
+
+use std::collections::HashMap;
+
+/// An event that drives a `ComponentIndex<T>` directly, without relying on change detection
+pub trait IndexEvent<T>: Send + Sync + 'static {
+    fn entity(&self) -> Entity;
+
+    /// The new key of the entity, or `None` if it should be dropped from the index
+    fn key(&self) -> Option<&T>;
+}
+
+impl<T: IndexKey> IndexEvent<T> for (Entity, T) {
+    fn entity(&self) -> Entity {
+        self.0
+    }
+
+    fn key(&self) -> Option<&T> {
+        Some(&self.1)
+    }
+}
+
+impl<T: IndexKey> IndexEvent<T> for (Entity, Option<T>) {
+    fn entity(&self) -> Entity {
+        self.0
+    }
+
+    fn key(&self) -> Option<&T> {
+        self.1.as_ref()
+    }
+}
+
+pub(crate) fn update_index_from_events<E: IndexEvent<T>, T: IndexKey>(
+    mut event_reader: Local<EventReader<E>>,
+    events: Res<Events<E>>,
+    mut index: ResMut<ComponentIndex<T>>,
+    mut metrics: ResMut<IndexMetrics<T>>,
+) {
+    if index.coalesce_changes {
+        // Only the last event for each entity matters, so skip the intermediate keys entirely.
+        // Entities are applied in the order of their first event, to keep the index deterministic.
+        let mut latest: Vec<(Entity, Option<&T>)> = Vec::new();
+        let mut positions: HashMap<Entity, usize> = HashMap::new();
+        for event in event_reader.iter(&events) {
+            match positions.get(&event.entity()) {
+                Some(&i) => latest[i].1 = event.key(),
+                None => {
+                    positions.insert(event.entity(), latest.len());
+                    latest.push((event.entity(), event.key()));
+                }
+            }
+        }
+
+        for (entity, key) in latest.into_iter() {
+            apply_change(&mut index, &mut metrics, entity, key);
+        }
+    } else {
+        for event in event_reader.iter(&events) {
+            apply_change(&mut index, &mut metrics, event.entity(), event.key());
+        }
+    }
+}
+
+fn apply_change<T: IndexKey>(
+    index: &mut ComponentIndex<T>,
+    metrics: &mut IndexMetrics<T>,
+    entity: Entity,
+    key: Option<&T>,
+) {
+    match key {
+        Some(key) => {
+            if !index.is_stored_as(entity, key) && index.validate(entity, key) {
+                index.insert(key.clone(), entity);
+                metrics.record_insert();
+            }
+        }
+        None => {
+            if index.value_of(entity).is_some() {
+                index.remove(&entity);
+                metrics.record_remove();
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct Tile(u8);
+
+    type TileEvent = (Entity, Option<Tile>);
+
+    #[test]
+    fn event_driven_index_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .add_event::<TileEvent>()
+            .init_index_from_events::<TileEvent, Tile>();
+
+        let moved = Entity::new(0);
+        let removed = Entity::new(1);
+        {
+            let mut events = app_builder
+                .resources()
+                .get_mut::<Events<TileEvent>>()
+                .unwrap();
+            events.send((moved, Some(Tile(0))));
+            events.send((removed, Some(Tile(0))));
+        }
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<Tile>>()
+                .unwrap();
+            assert_eq!(index.get(&Tile(0)).len(), 2);
+        }
+
+        {
+            let mut events = app_builder
+                .resources()
+                .get_mut::<Events<TileEvent>>()
+                .unwrap();
+            events.send((moved, Some(Tile(1))));
+            events.send((removed, None));
+        }
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Tile>>()
+            .unwrap();
+        assert!(index.get(&Tile(0)).is_empty());
+        assert_eq!(index.get(&Tile(1)).as_ref(), &[moved]);
+        assert_eq!(index.value_of(removed), None);
+    }
+
+    fn send_rapid_moves(app_builder: &mut AppBuilder, entity: Entity) {
+        let mut events = app_builder
+            .resources()
+            .get_mut::<Events<TileEvent>>()
+            .unwrap();
+        for tile in 0..5 {
+            events.send((entity, Some(Tile(tile))));
+        }
+    }
+
+    #[test]
+    fn coalescing_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .add_event::<TileEvent>()
+            .init_index_from_events::<TileEvent, Tile>();
+        app_builder
+            .resources()
+            .get_mut::<ComponentIndex<Tile>>()
+            .unwrap()
+            .set_coalescing(true);
+
+        let entity = Entity::new(0);
+        send_rapid_moves(&mut app_builder, entity);
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Tile>>()
+            .unwrap();
+        let metrics = app_builder.resources().get::<IndexMetrics<Tile>>().unwrap();
+        assert_eq!(metrics.inserts, 1);
+        assert_eq!(index.value_of(entity), Some(&Tile(4)));
+        assert!(index.get(&Tile(0)).is_empty());
+    }
+
+    #[test]
+    fn coalescing_order_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .add_event::<TileEvent>()
+            .init_index_from_events::<TileEvent, Tile>();
+        app_builder
+            .resources()
+            .get_mut::<ComponentIndex<Tile>>()
+            .unwrap()
+            .set_coalescing(true);
+
+        let entities: Vec<Entity> = (0..20).map(Entity::new).collect();
+        {
+            let mut events = app_builder
+                .resources()
+                .get_mut::<Events<TileEvent>>()
+                .unwrap();
+            for entity in entities.iter() {
+                events.send((*entity, Some(Tile(0))));
+            }
+            for entity in entities.iter() {
+                events.send((*entity, Some(Tile(1))));
+            }
+        }
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Tile>>()
+            .unwrap();
+        assert_eq!(index.get(&Tile(1)).as_ref(), entities.as_slice());
+    }
+
+    #[test]
+    fn no_coalescing_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .add_event::<TileEvent>()
+            .init_index_from_events::<TileEvent, Tile>();
+
+        let entity = Entity::new(0);
+        send_rapid_moves(&mut app_builder, entity);
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Tile>>()
+            .unwrap();
+        let metrics = app_builder.resources().get::<IndexMetrics<Tile>>().unwrap();
+        assert_eq!(metrics.inserts, 5);
+        assert_eq!(index.value_of(entity), Some(&Tile(4)));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::removal::iter_removed;
+use bevy::ecs::{QueryFilter, WorldQuery};
+use bevy::prelude::*;
+
+/// A query filter built out of `With`, `Without`, `Or` and tuples of them, for `ComponentIndexes::init_index_with_filter`.
+/// Whether an entity matches such a filter can only change when one of its components is added or removed,
+/// so the index only rechecks those entities rather than every entity it holds.
+pub trait IndexFilter: QueryFilter + Send + Sync + 'static {
+    /// Matches the entities that were given one of the filter's components this frame
+    type Gained: QueryFilter + Send + Sync + 'static;
+
+    /// Appends the entities that lost one of the filter's components this frame to `touched`.
+    /// As with `iter_removed`, `query` can be any query.
+    fn extend_removed<Q: WorldQuery, G: QueryFilter>(
+        query: &Query<'_, Q, G>,
+        touched: &mut Vec<Entity>,
+    );
+
+    /// Whether `entity` currently matches the filter, for updates made outside of the update system
+    fn matches(world: &World, entity: Entity) -> bool;
+}
+
+impl<C: Component> IndexFilter for With<C> {
+    type Gained = Added<C>;
+
+    fn extend_removed<Q: WorldQuery, G: QueryFilter>(
+        query: &Query<'_, Q, G>,
+        touched: &mut Vec<Entity>,
+    ) {
+        touched.extend(iter_removed::<C, _, _>(query));
+    }
+
+    fn matches(world: &World, entity: Entity) -> bool {
+        world.get::<C>(entity).is_ok()
+    }
+}
+
+impl<C: Component> IndexFilter for Without<C> {
+    type Gained = Added<C>;
+
+    fn extend_removed<Q: WorldQuery, G: QueryFilter>(
+        query: &Query<'_, Q, G>,
+        touched: &mut Vec<Entity>,
+    ) {
+        touched.extend(iter_removed::<C, _, _>(query));
+    }
+
+    fn matches(world: &World, entity: Entity) -> bool {
+        world.get::<C>(entity).is_err()
+    }
+}
+
+// Both combinators are touched whenever any of their parts are
+macro_rules! impl_index_filter_tuple {
+    ($($filter: ident),*) => {
+        impl<$($filter: IndexFilter),*> IndexFilter for ($($filter,)*) {
+            type Gained = Or<($(<$filter as IndexFilter>::Gained,)*)>;
+
+            fn extend_removed<Q: WorldQuery, G: QueryFilter>(
+                query: &Query<'_, Q, G>,
+                touched: &mut Vec<Entity>,
+            ) {
+                $(<$filter as IndexFilter>::extend_removed(query, touched);)*
+            }
+
+            fn matches(world: &World, entity: Entity) -> bool {
+                true $(&& <$filter as IndexFilter>::matches(world, entity))*
+            }
+        }
+
+        impl<$($filter: IndexFilter),*> IndexFilter for Or<($($filter,)*)> {
+            type Gained = Or<($(<$filter as IndexFilter>::Gained,)*)>;
+
+            fn extend_removed<Q: WorldQuery, G: QueryFilter>(
+                query: &Query<'_, Q, G>,
+                touched: &mut Vec<Entity>,
+            ) {
+                $(<$filter as IndexFilter>::extend_removed(query, touched);)*
+            }
+
+            fn matches(world: &World, entity: Entity) -> bool {
+                false $(|| <$filter as IndexFilter>::matches(world, entity))*
+            }
+        }
+    };
+}
+
+impl_index_filter_tuple!(A);
+impl_index_filter_tuple!(A, B);
+impl_index_filter_tuple!(A, B, C);
+impl_index_filter_tuple!(A, B, C, D);
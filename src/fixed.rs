@@ -0,0 +1,94 @@
+use crate::{ComponentIndex, IndexKey};
+use bevy::ecs::ShouldRun;
+use bevy::prelude::*;
+
+/// The stage that indexes initialized with `ComponentIndexes::init_index_fixed` are updated in.
+/// It runs right after `POST_UPDATE`, but only on frames where an `IndexTimestep` tick is due.
+/// Indexes updated in it are compared against the whole world on each tick.
+pub const FIXED_INDEX_UPDATE: &str = "fixed_index_update";
+
+// Marks that the FIXED_INDEX_UPDATE stage has been added to the app
+pub(crate) struct FixedStage;
+
+/// The fixed timestep shared by every index updated in `FIXED_INDEX_UPDATE`.
+/// Time is accumulated from the `Time` resource each frame, and can also be advanced by hand.
+#[derive(Debug)]
+pub struct IndexTimestep {
+    step: f64,
+    accumulator: f64,
+}
+
+impl IndexTimestep {
+    /// A timestep that ticks once every `step` seconds
+    pub fn new(step: f64) -> Self {
+        assert!(step > 0.0, "IndexTimestep step must be positive");
+
+        IndexTimestep {
+            step,
+            accumulator: 0.0,
+        }
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Adds `seconds` to the time accumulated towards the next tick
+    pub fn advance(&mut self, seconds: f64) {
+        self.accumulator += seconds;
+    }
+
+    // Consumes every tick that is due, returning whether there was at least one.
+    // Updating an index is idempotent, so several ticks in one frame only need a single update.
+    fn consume_ticks(&mut self) -> bool {
+        if self.accumulator < self.step {
+            return false;
+        }
+        self.accumulator %= self.step;
+        true
+    }
+}
+
+/// Ticks 60 times per second
+impl Default for IndexTimestep {
+    fn default() -> Self {
+        IndexTimestep::new(1.0 / 60.0)
+    }
+}
+
+pub(crate) fn accumulate_index_time(time: Res<Time>, mut timestep: ResMut<IndexTimestep>) {
+    timestep.advance(time.delta_seconds_f64());
+}
+
+pub(crate) fn index_tick(mut timestep: ResMut<IndexTimestep>) -> ShouldRun {
+    if timestep.consume_ticks() {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+// Change flags and removals are only kept for a single frame, so those from frames without a tick
+// are gone by the next tick. Each tick compares the index against the whole world instead.
+pub(crate) fn resync_on_tick<T: IndexKey>(mut index: ResMut<ComponentIndex<T>>) {
+    index.resync_pending = true;
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn consume_ticks_test() {
+        let mut timestep = IndexTimestep::new(0.5);
+        timestep.advance(0.25);
+        assert!(!timestep.consume_ticks());
+
+        // Catching up on several ticks at once leaves the remainder for the next one
+        timestep.advance(1.0);
+        assert!(timestep.consume_ticks());
+        assert!(!timestep.consume_ticks());
+        timestep.advance(0.25);
+        assert!(timestep.consume_ticks());
+    }
+}
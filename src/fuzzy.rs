@@ -0,0 +1,191 @@
+use crate::range::is_empty_range;
+use crate::removal::iter_removed;
+use bevy::prelude::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, RangeBounds};
+
+/// An index over a float derived from the component `C`, quantized into buckets of `cell_size`.
+/// Lookups and range queries operate on whole buckets,
+/// so any value in `[n * cell_size, (n + 1) * cell_size)` is treated as equal.
+pub struct FuzzyIndex<C> {
+    cell_size: f32,
+    project: fn(&C) -> f32,
+    forward: BTreeMap<i64, Vec<Entity>>,
+    reverse: HashMap<Entity, i64>,
+}
+
+impl<C> FuzzyIndex<C> {
+    pub fn new(cell_size: f32, project: fn(&C) -> f32) -> Self {
+        assert!(cell_size > 0.0, "FuzzyIndex cell size must be positive");
+
+        FuzzyIndex::<C> {
+            cell_size,
+            project,
+            forward: BTreeMap::new(),
+            reverse: HashMap::new(),
+        }
+    }
+
+    /// The bucket that `value` falls into
+    pub fn bucket_of(&self, value: f32) -> i64 {
+        (value / self.cell_size).floor() as i64
+    }
+
+    /// All entities whose value falls into the same bucket as `value`
+    pub fn get(&self, value: f32) -> &[Entity] {
+        match self.forward.get(&self.bucket_of(value)) {
+            Some(e) => e,
+            None => &[],
+        }
+    }
+
+    /// All entities whose bucket lies within `range`.
+    /// The bounds are quantized before being applied, so an excluded bound excludes its whole bucket.
+    pub fn get_range<R: RangeBounds<f32>>(&self, range: R) -> Vec<Entity> {
+        let start = self.quantize_bound(range.start_bound());
+        let end = self.quantize_bound(range.end_bound());
+
+        if is_empty_range(start.as_ref(), end.as_ref()) {
+            return Vec::new();
+        }
+
+        self.forward
+            .range((start, end))
+            .flat_map(|(_, entities)| entities.iter().copied())
+            .collect()
+    }
+
+    /// The bucket that `entity` is currently stored under
+    pub fn bucket_of_entity(&self, entity: Entity) -> Option<i64> {
+        self.reverse.get(&entity).copied()
+    }
+
+    fn quantize_bound(&self, bound: Bound<&f32>) -> Bound<i64> {
+        match bound {
+            Bound::Included(value) => Bound::Included(self.bucket_of(*value)),
+            Bound::Excluded(value) => Bound::Excluded(self.bucket_of(*value)),
+            Bound::Unbounded => Bound::Unbounded,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, component: &C, entity: Entity) {
+        self.remove(&entity);
+
+        let bucket = self.bucket_of((self.project)(component));
+        self.forward
+            .entry(bucket)
+            .or_insert_with(Vec::new)
+            .push(entity);
+        self.reverse.insert(entity, bucket);
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) {
+        if let Some(old_bucket) = self.reverse.remove(entity) {
+            if let Some(entities) = self.forward.get_mut(&old_bucket) {
+                entities.retain(|e| e != entity);
+                if entities.is_empty() {
+                    self.forward.remove(&old_bucket);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn update_fuzzy_index<C: Component>(
+    mut index: ResMut<FuzzyIndex<C>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        index.insert(component, entity);
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    struct Height(f32);
+
+    fn height(h: &Height) -> f32 {
+        h.0
+    }
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn fuzzy_get_test() {
+        let mut index = FuzzyIndex::new(1.0, height);
+        let (a, b, c) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        index.insert(&Height(0.2), a);
+        index.insert(&Height(0.9), b);
+        index.insert(&Height(-0.1), c);
+
+        assert_eq!(sorted(index.get(0.5).to_vec()), vec![a, b]);
+        assert_eq!(index.get(-0.5), &[c]);
+        assert!(index.get(3.0).is_empty());
+
+        index.insert(&Height(3.3), b);
+        assert_eq!(index.get(0.5), &[a]);
+        assert_eq!(index.bucket_of_entity(b), Some(3));
+
+        // Buckets left empty are dropped, so moving values don't leave a trail of them behind
+        index.insert(&Height(3.5), c);
+        assert!(index.get(-0.5).is_empty());
+        assert_eq!(index.forward.len(), 2);
+        assert!(!index.forward.contains_key(&-1));
+    }
+
+    #[test]
+    fn fuzzy_range_test() {
+        let mut index = FuzzyIndex::new(0.5, height);
+        let entities: Vec<Entity> = (0..6).map(Entity::new).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            // Buckets 0, 1, 2, 3, 4, 5
+            index.insert(&Height(i as f32 * 0.5 + 0.1), *entity);
+        }
+
+        assert_eq!(sorted(index.get_range(0.5..=1.5)), entities[1..=3].to_vec());
+        assert_eq!(sorted(index.get_range(0.5..1.5)), entities[1..3].to_vec());
+        assert_eq!(
+            sorted(index.get_range((Bound::Excluded(0.5), Bound::Included(1.5)))),
+            entities[2..=3].to_vec()
+        );
+        assert_eq!(sorted(index.get_range(..1.0)), entities[0..2].to_vec());
+        assert_eq!(sorted(index.get_range(2.0..)), entities[4..].to_vec());
+
+        // Bounds within the same bucket
+        assert_eq!(index.get_range(1.0..=1.2), vec![entities[2]]);
+        assert!(index.get_range(1.0..1.2).is_empty());
+        assert!(index.get_range(2.0..=1.0).is_empty());
+    }
+
+    #[test]
+    fn fuzzy_update_test() {
+        let mut app_builder = App::build();
+        app_builder.init_fuzzy_index::<Height>(1.0, height);
+        let entity = app_builder.app.world.spawn((Height(4.5),));
+        app_builder.app.update();
+
+        {
+            let index = app_builder.resources().get::<FuzzyIndex<Height>>().unwrap();
+            assert_eq!(index.get_range(4.0..5.0), vec![entity]);
+        }
+
+        app_builder.app.world.get_mut::<Height>(entity).unwrap().0 = 7.2;
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<FuzzyIndex<Height>>().unwrap();
+        assert!(index.get_range(4.0..5.0).is_empty());
+        assert_eq!(index.get(7.9), &[entity]);
+    }
+}
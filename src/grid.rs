@@ -0,0 +1,231 @@
+use crate::removal::iter_removed;
+use crate::ComponentIndex;
+use bevy::prelude::*;
+
+use std::borrow::Cow;
+
+/// A cell of a two-dimensional grid
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GridCell {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// The size of the grid covered by a `GridIndex`, spanning `0..width` and `0..height`.
+/// Toroidal grids wrap around, so that cells on one edge neighbor the cells on the opposite edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridTopology {
+    pub width: i32,
+    pub height: i32,
+    pub wrap: bool,
+}
+
+impl GridTopology {
+    /// Maps `cell` onto the grid, or returns `None` if it lies off the edge of a grid that doesn't wrap
+    pub fn normalize(&self, cell: GridCell) -> Option<GridCell> {
+        if self.wrap {
+            Some(GridCell {
+                x: cell.x.rem_euclid(self.width),
+                y: cell.y.rem_euclid(self.height),
+            })
+        } else if (0..self.width).contains(&cell.x) && (0..self.height).contains(&cell.y) {
+            Some(cell)
+        } else {
+            None
+        }
+    }
+
+    /// The cells within `radius` steps of `cell`, diagonals included, excluding `cell` itself.
+    /// Each cell is only listed once, even if a large radius wraps all the way around the grid.
+    pub fn neighbors(&self, cell: GridCell, radius: i32) -> Vec<GridCell> {
+        let center = self.normalize(cell);
+        let mut neighbors = Vec::new();
+        for dx in -radius..=radius {
+            for dy in -radius..=radius {
+                let candidate = self.normalize(GridCell {
+                    x: cell.x + dx,
+                    y: cell.y + dy,
+                });
+                if let Some(candidate) = candidate {
+                    if Some(candidate) != center && !neighbors.contains(&candidate) {
+                        neighbors.push(candidate);
+                    }
+                }
+            }
+        }
+        neighbors
+    }
+}
+
+/// An index over the `GridCell` that each `C` occupies, supporting neighborhood queries.
+/// On a wrapping grid, cells are stored wrapped onto the grid.
+pub struct GridIndex<C> {
+    topology: GridTopology,
+    project: fn(&C) -> GridCell,
+    cells: ComponentIndex<GridCell>,
+}
+
+impl<C> GridIndex<C> {
+    pub fn new(topology: GridTopology, project: fn(&C) -> GridCell) -> Self {
+        assert!(
+            topology.width > 0 && topology.height > 0,
+            "GridIndex dimensions must be positive"
+        );
+
+        GridIndex::<C> {
+            topology,
+            project,
+            cells: ComponentIndex::new(),
+        }
+    }
+
+    pub fn topology(&self) -> GridTopology {
+        self.topology
+    }
+
+    /// The entities occupying `cell`
+    pub fn get(&self, cell: GridCell) -> Cow<'_, [Entity]> {
+        match self.topology.normalize(cell) {
+            Some(cell) => self.cells.get(&cell),
+            None => Cow::from(Vec::new()),
+        }
+    }
+
+    /// The entities occupying any cell within `radius` steps of `cell`, but not `cell` itself
+    pub fn get_neighbors(&self, cell: GridCell, radius: i32) -> Vec<Entity> {
+        self.topology
+            .neighbors(cell, radius)
+            .into_iter()
+            .flat_map(|neighbor| self.cells.get(&neighbor).into_owned())
+            .collect()
+    }
+
+    pub fn cell_of(&self, entity: Entity) -> Option<GridCell> {
+        self.cells.value_of(entity).copied()
+    }
+
+    pub(crate) fn insert(&mut self, component: &C, entity: Entity) {
+        match self.topology.normalize((self.project)(component)) {
+            Some(cell) => {
+                if !self.cells.is_stored_as(entity, &cell) {
+                    self.cells.insert(cell, entity);
+                }
+            }
+            // Off the edge of the grid, so it can't be anyone's neighbor
+            None => self.cells.remove(&entity),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) {
+        self.cells.remove(entity);
+    }
+}
+
+pub(crate) fn update_grid_index<C: Component>(
+    mut index: ResMut<GridIndex<C>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        index.insert(component, entity);
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    struct Tile(i32, i32);
+
+    fn tile_cell(tile: &Tile) -> GridCell {
+        GridCell {
+            x: tile.0,
+            y: tile.1,
+        }
+    }
+
+    fn cell(x: i32, y: i32) -> GridCell {
+        GridCell { x, y }
+    }
+
+    fn sorted<T: Ord>(mut items: Vec<T>) -> Vec<T> {
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn wrapping_neighbors_test() {
+        let torus = GridTopology {
+            width: 4,
+            height: 3,
+            wrap: true,
+        };
+        assert_eq!(
+            sorted(torus.neighbors(cell(0, 0), 1)),
+            sorted(vec![
+                cell(3, 2),
+                cell(0, 2),
+                cell(1, 2),
+                cell(3, 0),
+                cell(1, 0),
+                cell(3, 1),
+                cell(0, 1),
+                cell(1, 1),
+            ])
+        );
+        // The opposite corner wraps back around to the origin
+        assert!(torus.neighbors(cell(3, 2), 1).contains(&cell(0, 0)));
+        // A radius wider than the grid covers every other cell exactly once
+        assert_eq!(torus.neighbors(cell(0, 0), 5).len(), 11);
+
+        let bounded = GridTopology {
+            wrap: false,
+            ..torus
+        };
+        assert_eq!(
+            sorted(bounded.neighbors(cell(0, 0), 1)),
+            vec![cell(0, 1), cell(1, 0), cell(1, 1)]
+        );
+    }
+
+    #[test]
+    fn grid_index_update_test() {
+        let mut app_builder = App::build();
+        app_builder.init_grid_index::<Tile>(
+            GridTopology {
+                width: 4,
+                height: 3,
+                wrap: true,
+            },
+            tile_cell,
+        );
+        let world = &mut app_builder.app.world;
+        let across_corner = world.spawn((Tile(3, 2),));
+        let across_edge = world.spawn((Tile(0, 2),));
+        let adjacent = world.spawn((Tile(1, 1),));
+        let far = world.spawn((Tile(2, 1),));
+        app_builder.app.update();
+
+        {
+            let index = app_builder.resources().get::<GridIndex<Tile>>().unwrap();
+            assert_eq!(
+                sorted(index.get_neighbors(cell(0, 0), 1)),
+                sorted(vec![across_corner, across_edge, adjacent])
+            );
+        }
+
+        // Walking off the right edge wraps around to the left
+        app_builder.app.world.get_mut::<Tile>(far).unwrap().0 = 4;
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<GridIndex<Tile>>().unwrap();
+        assert_eq!(index.cell_of(far), Some(cell(0, 1)));
+        assert_eq!(index.get(cell(-4, 1)).as_ref(), &[far]);
+        assert_eq!(index.get_neighbors(cell(0, 0), 1).len(), 4);
+    }
+}
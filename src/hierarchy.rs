@@ -0,0 +1,109 @@
+use crate::removal::iter_removed;
+use crate::{ComponentIndex, IndexKey};
+use bevy::prelude::*;
+
+use std::collections::HashSet;
+
+/// The function used to compute the key of each child from its parent's `C`,
+/// for the `ComponentIndex<K>` it feeds
+pub struct ParentProjection<C, K> {
+    pub(crate) project: fn(&C) -> K,
+}
+
+pub(crate) fn update_parent_projection_index<C: Component, K: IndexKey>(
+    mut index: ResMut<ComponentIndex<K>>,
+    projection: Res<ParentProjection<C, K>>,
+    child_query: Query<(&Parent, Entity)>,
+    reparented_query: Query<(&Parent, Entity), Changed<Parent>>,
+    parent_query: Query<&C>,
+    changed_parent_query: Query<Entity, Changed<C>>,
+) {
+    for entity in iter_removed::<Parent, _, _>(&child_query) {
+        index.remove(&entity);
+    }
+
+    for (parent, entity) in reparented_query.iter() {
+        reproject(&mut index, &projection, &parent_query, parent.0, entity);
+    }
+
+    // Parents whose key changed or disappeared take all of their children with them
+    let touched_parents: HashSet<Entity> = changed_parent_query
+        .iter()
+        .chain(iter_removed::<C, _, _>(&parent_query))
+        .collect();
+    if touched_parents.is_empty() {
+        return;
+    }
+    for (parent, entity) in child_query.iter() {
+        if touched_parents.contains(&parent.0) {
+            reproject(&mut index, &projection, &parent_query, parent.0, entity);
+        }
+    }
+}
+
+fn reproject<C: Component, K: IndexKey>(
+    index: &mut ComponentIndex<K>,
+    projection: &ParentProjection<C, K>,
+    parent_query: &Query<&C>,
+    parent: Entity,
+    entity: Entity,
+) {
+    match parent_query.get(parent) {
+        Ok(component) => {
+            let key = (projection.project)(component);
+            if !index.is_stored_as(entity, &key) {
+                index.insert(key, entity);
+            }
+        }
+        Err(_) => {
+            if index.value_of(entity).is_some() {
+                index.remove(&entity);
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    #[cfg(test)]
+    use crate::test_utils::assert_indexed;
+    use crate::ComponentIndexes;
+
+    struct Room(u8);
+
+    fn room_number(room: &Room) -> u8 {
+        room.0
+    }
+
+    #[test]
+    fn reparenting_test() {
+        let mut app_builder = App::build();
+        app_builder.init_parent_projection::<Room, u8>(room_number);
+        let world = &mut app_builder.app.world;
+        let kitchen = world.spawn((Room(1),));
+        let cellar = world.spawn((Room(2),));
+        let cat = world.spawn((Parent(kitchen),));
+        let dog = world.spawn((Parent(kitchen),));
+        app_builder.app.update();
+        assert_indexed(&app_builder.app, &1, &[cat, dog]);
+
+        *app_builder.app.world.get_mut::<Parent>(cat).unwrap() = Parent(cellar);
+        app_builder.app.update();
+        assert_indexed(&app_builder.app, &1, &[dog]);
+        assert_indexed(&app_builder.app, &2, &[cat]);
+
+        // Renumbering the parent moves its children along
+        app_builder.app.world.get_mut::<Room>(cellar).unwrap().0 = 3;
+        app_builder.app.update();
+        assert_indexed(&app_builder.app, &2, &[]);
+        assert_indexed(&app_builder.app, &3, &[cat]);
+
+        app_builder.app.world.remove_one::<Parent>(dog).unwrap();
+        app_builder.app.world.remove_one::<Room>(cellar).unwrap();
+        app_builder.app.update();
+        let index = app_builder.resources().get::<ComponentIndex<u8>>().unwrap();
+        assert_eq!(index.value_of(dog), None);
+        assert_eq!(index.value_of(cat), None);
+    }
+}
@@ -0,0 +1,26 @@
+use bevy::prelude::*;
+
+use std::hash::Hash;
+
+/// Any component that can be hashed, compared and cloned can be used as a key,
+/// including tuples and fixed-size arrays such as `[i8; 3]` coordinates
+// Older compilers reject the `diagnostic` namespace, so the friendlier errors are limited to 1.78 onwards
+#[rustversion::attr(
+    since(1.78),
+    diagnostic::on_unimplemented(
+        message = "`{Self}` can't be used as an index key",
+        label = "not an index key",
+        note = "index keys must implement `Hash`, `Eq` and `Clone`, and be `Send + Sync + 'static` like any component",
+        note = "try adding `#[derive(Clone, Hash, PartialEq, Eq)]` to the definition of `{Self}`"
+    )
+)]
+pub trait IndexKey: Component + Eq + Hash + Clone {}
+impl<T: Component + Eq + Hash + Clone> IndexKey for T {}
+
+/// A key type with a coarser notion of equality than `Eq`,
+/// such as case-insensitive strings or positions on a wrapping grid
+pub trait IndexEquiv: IndexKey {
+    /// Returns the representative of this key's equivalence class.
+    /// Equivalent keys must return equal representatives.
+    fn canonical(&self) -> Self;
+}
@@ -1,13 +1,91 @@
+use bevy::asset::Asset;
+use bevy::core::Name;
+use bevy::ecs::{Command, QueryError, ShouldRun, System};
+use bevy::log::warn;
 use bevy::prelude::*;
-use multimap::MultiMap;
+use bevy::tasks::{ParallelSlice, TaskPool};
+use multimap::{Entry, MultiMap};
+#[cfg(feature = "smallvec")]
+use smallvec::SmallVec;
 
-use std::borrow::Cow;
-use std::collections::HashMap;
+use std::any::type_name;
+use std::borrow::{Borrow, Cow};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Debug, Display};
 use std::hash::Hash;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+mod adjacency;
+pub use adjacency::{AdjacencyIndex, Edge};
+mod borrowed;
+pub use borrowed::BorrowedProjection;
+mod builder;
+pub use builder::IndexBuilder;
+mod cached;
+pub use cached::CachedIndex;
+mod case_insensitive;
+pub use case_insensitive::{CaseInsensitiveString, CaseInsensitiveStringIndex};
+mod category;
+pub use category::CategoryProjection;
+mod changes;
+pub use changes::{
+    ChangeEventMode, IndexBatchChanged, IndexChange, IndexChanged, IndexSink, KeyOccupancyChanged,
+    WatchedKeys,
+};
+mod display;
+pub use display::DisplayIndex;
+mod events;
+pub use events::IndexEvent;
+mod filter;
+pub use filter::IndexFilter;
+mod fixed;
+pub use fixed::{IndexTimestep, FIXED_INDEX_UPDATE};
+mod fuzzy;
+pub use fuzzy::FuzzyIndex;
+mod grid;
+pub use grid::{GridCell, GridIndex, GridTopology};
+mod hierarchy;
+pub use hierarchy::ParentProjection;
+mod key;
+pub use key::{IndexEquiv, IndexKey};
+mod lookup;
+pub use lookup::LookupKey;
+mod lru;
+pub use lru::LruIndex;
+mod membership;
+pub use membership::{EntityList, MembershipIndex};
+mod metrics;
+pub use metrics::IndexMetrics;
+mod multi_key;
+pub use multi_key::{KeyProjection, MultiKeyIndex};
+mod projection;
+pub use projection::{MultiProjectionBuilder, ProjectionIndexes};
+mod range;
+pub use range::RangeIndex;
+mod read;
+pub use read::IndexRead;
+mod removal;
+use removal::iter_removed;
+mod sharded;
+pub use sharded::ShardedIndex;
+mod sparse;
+pub use sparse::SparseIndex;
+mod tag;
+pub use tag::TagIndex;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+mod time_bucket;
+pub use time_bucket::TimeBucketIndex;
+mod tuple;
 
 // IDEA: Can we instead implicitly declare indexes by passing in a ComponentIndex<T> to our systems?
 // We don't actually want the full resource structure, since these should never be manually updated
-#[derive(Debug, PartialEq, Eq)]
+/// Maps each key to the entities whose component has that value.
+/// Systems read it as `Res<ComponentIndex<T>>`, while exclusive systems that take
+/// `&mut World` and `&mut Resources` can fetch it with `resources.get::<ComponentIndex<T>>()`.
+/// Either way, the index is only guaranteed to be up to date after its update system has run.
+#[derive(Debug)]
 pub struct ComponentIndex<T: Hash + Eq> {
     // TODO: we can speed this up by changing reverse to be a Hashmap<Entity, Hash<T>>, then feeding those directly back into forward
     // This prevents us from ever having to store the unhashed T, which can be significantly sized (requires unstable functionality)
@@ -15,11 +93,69 @@ pub struct ComponentIndex<T: Hash + Eq> {
     // TODO: How can we improve memory locality on this data structure
     forward: MultiMap<T, Entity>,
     reverse: HashMap<Entity, T>,
+
+    // Time-to-live bookkeeping, measured in the units of the TtlClock driving this index
+    clock: f64,
+    default_ttl: Option<f64>,
+    expiries: HashMap<Entity, f64>,
+
+    // Normalizes keys before they're stored or looked up,
+    // such as mapping them to the representative of their equivalence class (see IndexEquiv)
+    canonicalize: Option<fn(&T) -> T>,
+
+    // Keys failing this check are never indexed
+    validator: Option<fn(&T) -> bool>,
+
+    // Entities failing this check are never indexed, for indexes restricted to entities matching a filter.
+    // The update systems apply the filter through their queries, so only updates made outside of them use it.
+    filter: Option<fn(&World, Entity) -> bool>,
+
+    // Whether event-driven updates only apply the last change to each entity per frame
+    coalesce_changes: bool,
+
+    consistency_policy: ConsistencyPolicy,
+    drop_empty_keys: bool,
+
+    // Emptied entity lists of dropped keys, kept around to be reused by new keys
+    spare_vecs: Vec<Vec<Entity>>,
+    spare_vecs_limit: usize,
+
+    // While paused, change detection is ignored; a full resync runs once updates resume
+    paused: bool,
+    resync_pending: bool,
+
+    // While recording, every insert and removal is appended here
+    log: Option<Vec<IndexOp<T>>>,
+
+    // When tracked, the order in which entities were first indexed, across all keys
+    insertion_order: Option<InsertionOrder>,
+
+    // What the most recent pass of the update system did
+    last_update: UpdateStats,
+
+    // Per-frame bookkeeping, cleared at the start of each frame.
+    // The entity lists are only filled while tracked, so indexes that nothing clears don't grow without bound.
+    track_frame_changes: bool,
+    reassigned: Vec<Entity>,
+    reassigned_set: HashSet<Entity>,
+    added: Vec<Entity>,
+    evicted: Vec<Entity>,
+    changed: bool,
+    updated: bool,
 }
 
 impl<T: Hash + Eq> ComponentIndex<T> {
-    pub fn get(&self, component_val: &T) -> Cow<'_, [Entity]> {
-        match self.forward.get_vec(component_val) {
+    /// The entities stored under `component_val`, in the order they were stored there.
+    /// Removing an entity leaves the others in order, so the result is deterministic
+    /// for a given sequence of updates.
+    /// Like `HashMap::get`, the key can be passed in any form that `T` borrows as,
+    /// such as a `&str` for an index over `String`s, see `LookupKey`.
+    pub fn get<Q>(&self, component_val: &Q) -> Cow<'_, [Entity]>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        match self.entities_of(component_val) {
             Some(e) => Cow::from(e),
             None => Cow::from(Vec::new()),
         }
@@ -29,320 +165,4445 @@ impl<T: Hash + Eq> ComponentIndex<T> {
         ComponentIndex::<T>::default()
     }
 
-    fn remove(&mut self, entity: &Entity) {
-        let old_component = &self.reverse.get(&entity);
-        if old_component.is_some() {
-            self.forward
-                .retain(|k, v| (k == old_component.unwrap()) && (v != entity));
-            self.reverse.remove(entity);
+    /// Creates an empty index with room for `capacity` entities
+    pub fn with_capacity(capacity: usize) -> Self {
+        ComponentIndex::<T> {
+            forward: MultiMap::with_capacity(capacity),
+            reverse: HashMap::with_capacity(capacity),
+            ..ComponentIndex::default()
         }
     }
 
-    // TODO: add manual_update function for multi-stage flow
+    /// The entity that was stored under `component_val` the longest ago,
+    /// or `None` if no entities currently have that key
+    pub fn first<Q>(&self, component_val: &Q) -> Option<Entity>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        self.entities_of(component_val)
+            .and_then(|e| e.first().copied())
+    }
 
-    // TODO: add clean function to remove unused keys and fix memory locality
-}
+    /// The entity that was stored under `component_val` most recently,
+    /// or `None` if no entities currently have that key
+    pub fn last<Q>(&self, component_val: &Q) -> Option<Entity>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        self.entities_of(component_val)
+            .and_then(|e| e.last().copied())
+    }
 
-impl<T: Hash + Eq> Default for ComponentIndex<T> {
-    fn default() -> Self {
-        ComponentIndex::<T> {
-            forward: MultiMap::new(),
-            reverse: HashMap::new(),
+    /// The entity with the smallest id stored under `component_val`.
+    /// Unlike `first`, this doesn't depend on the order entities were stored in,
+    /// so it picks the same entity in every run of a reproducible simulation.
+    pub fn get_one_min<Q>(&self, component_val: &Q) -> Option<Entity>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        self.entities_of(component_val)
+            .and_then(|e| e.iter().min().copied())
+    }
+
+    /// The entities stored under `component_val`, copied into a `SmallVec` that holds up to `N` entities inline.
+    /// Unlike `get`, the result doesn't borrow the index, yet lookups of keys with few entities never allocate.
+    /// Requires the `smallvec` feature.
+    #[cfg(feature = "smallvec")]
+    pub fn get_smallvec<const N: usize, Q>(&self, component_val: &Q) -> SmallVec<[Entity; N]>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        match self.entities_of(component_val) {
+            Some(e) => SmallVec::from_slice(e),
+            None => SmallVec::new(),
         }
     }
-}
 
-pub trait IndexKey: Component + Eq + Hash + Clone {}
-impl<T: Component + Eq + Hash + Clone> IndexKey for T {}
+    /// The number of entities stored under `component_val`, in constant time.
+    /// This is read straight off the key's entity list rather than kept as a separate count,
+    /// so it can never drift from `get`, and keys left empty by removals count as 0.
+    pub fn entities_len<Q>(&self, component_val: &Q) -> usize
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        self.entities_of(component_val).map_or(0, |e| e.len())
+    }
 
-pub trait ComponentIndexes {
-    fn init_index<T: IndexKey>(&mut self) -> &mut Self;
+    /// The total number of entities stored under keys that satisfy `predicate`
+    pub fn count_where<F: Fn(&T) -> bool>(&self, predicate: F) -> usize {
+        self.forward
+            .iter_all()
+            .filter(|(key, _)| predicate(key))
+            .map(|(_, entities)| entities.len())
+            .sum()
+    }
 
-    fn update_component_index<T: IndexKey>(
-        index: ResMut<ComponentIndex<T>>,
-        query: Query<(&T, Entity)>,
-        changed_query: Query<(&T, Entity), Changed<T>>,
-    );
-}
+    /// The number of distinct keys that currently have at least one entity
+    pub fn key_count(&self) -> usize {
+        self.forward
+            .iter_all()
+            .filter(|(_, entities)| !entities.is_empty())
+            .count()
+    }
 
-impl ComponentIndexes for AppBuilder {
-    fn init_index<T: IndexKey>(&mut self) -> &mut Self {
-        self.init_resource::<ComponentIndex<T>>();
-        // FIXME: this should instead be run automatically whenever an index is used
-        // Otherwise there's no guarantee it's fresh
-        // Will also need to add a copy to LAST
-        self.add_startup_system_to_stage(
-            "post_startup",
-            Self::update_component_index::<T>.system(),
-        );
-        self.add_system_to_stage(
-            stage::POST_UPDATE,
-            Self::update_component_index::<T>.system(),
-        );
+    /// Like `get`, but distinguishes keys that have been emptied from keys that were never indexed
+    pub fn lookup<Q>(&self, component_val: &Q) -> Lookup<'_>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        match self.entities_of(component_val) {
+            None => Lookup::Missing,
+            Some(e) if e.is_empty() => Lookup::Empty,
+            Some(e) => Lookup::Entities(e),
+        }
+    }
 
-        self
+    /// Calls `f` on each entity stored under `component_val`, spreading the work over `task_pool`
+    pub fn par_for_each<Q, F: Fn(Entity) + Send + Sync>(
+        &self,
+        task_pool: &TaskPool,
+        component_val: &Q,
+        f: F,
+    ) where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        if let Some(entities) = self.entities_of(component_val) {
+            entities.par_splat_map(task_pool, None, |chunk| {
+                for &entity in chunk {
+                    f(entity);
+                }
+            });
+        }
     }
 
-    fn update_component_index<T: IndexKey>(
-        mut index: ResMut<ComponentIndex<T>>,
-        query: Query<(&T, Entity)>,
-        changed_query: Query<(&T, Entity), Changed<T>>,
-    ) {
-        // First, clean up any entities who had this component removed
-        for entity in query.removed::<T>().iter() {
-            index.remove(entity);
+    /// Applies `f` to each entity stored under `component_val`, collecting the results
+    pub fn get_mapped<Q, R, F: FnMut(Entity) -> R>(&self, component_val: &Q, f: F) -> Vec<R>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        match self.entities_of(component_val) {
+            Some(entities) => entities.iter().copied().map(f).collect(),
+            None => Vec::new(),
         }
+    }
 
-        for (component, entity) in changed_query.iter() {
-            index.remove(&entity);
+    /// Each entity stored under `component_val`, paired with its `C` as fetched from `query`.
+    /// Entities without a `C` are skipped.
+    pub fn get_with<'q, Q, C: Component>(
+        &self,
+        component_val: &Q,
+        query: &'q Query<'_, &C>,
+    ) -> Vec<(Entity, &'q C)>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        match self.entities_of(component_val) {
+            Some(entities) => entities
+                .iter()
+                .filter_map(|&entity| query.get(entity).ok().map(|c| (entity, c)))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Each entity stored under `component_val`, paired with the result of fetching its `C` from `query`.
+    /// Unlike `get_with`, entities without a `C` are kept, so callers can fall back to a default
+    /// rather than unwrapping each fetch.
+    pub fn get_components<'a, Q, C: Component>(
+        &'a self,
+        component_val: &Q,
+        query: &'a Query<'a, &C>,
+    ) -> impl Iterator<Item = (Entity, Result<&'a C, QueryError>)> + 'a
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        let entities: &'a [Entity] = self.entities_of(component_val).map_or(&[], |e| e);
+        entities
+            .iter()
+            .map(move |&entity| (entity, query.get(entity)))
+    }
+
+    /// Whether `entity` is stored under any key
+    pub fn is_indexed(&self, entity: Entity) -> bool {
+        self.reverse.contains_key(&entity)
+    }
+
+    pub fn value_of(&self, entity: Entity) -> Option<&T> {
+        self.reverse.get(&entity)
+    }
+
+    /// All entities stored under the same key as `entity`, or nothing if `entity` isn't indexed
+    pub fn siblings(&self, entity: Entity, include_self: bool) -> Cow<'_, [Entity]> {
+        let entities = match self.value_of(entity).and_then(|k| self.forward.get_vec(k)) {
+            Some(e) => e,
+            None => return Cow::from(Vec::new()),
+        };
 
-            // Add in new values for the changed records to the forward and reverse entries
-            index.forward.insert(component.clone(), entity);
-            index.reverse.insert(entity, component.clone());
+        if include_self {
+            Cow::from(entities)
+        } else {
+            Cow::from(
+                entities
+                    .iter()
+                    .copied()
+                    .filter(|e| *e != entity)
+                    .collect::<Vec<Entity>>(),
+            )
         }
     }
-}
 
-#[allow(dead_code)]
-mod test {
+    /// The key `entity` is stored under, or `default` if it isn't indexed
+    pub fn value_of_or<'a>(&'a self, entity: Entity, default: &'a T) -> &'a T {
+        self.value_of(entity).unwrap_or(default)
+    }
 
-    use super::*;
-    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
-    struct MyStruct {
-        val: i8,
+    /// When enabled, an index driven by events only applies the last key sent for each entity each frame,
+    /// skipping the intermediate ones.
+    /// Change detection only ever sees the final value of each frame, so other indexes are unaffected.
+    pub fn set_coalescing(&mut self, coalesce: bool) {
+        self.coalesce_changes = coalesce;
     }
 
-    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
-    struct MyTupleStruct(i8);
-    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
-    struct MyCompoundStruct {
-        val: i8,
-        name: String,
+    /// Freezes the index in its current state, for example during cutscenes or while editing.
+    /// Changes made while paused are caught up on with a full pass over the world once resumed.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            self.resync_pending = true;
+        }
+        self.paused = paused;
     }
-    #[allow(dead_code)]
-    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
-    enum MyEnum {
-        Red,
-        Blue,
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
     }
 
-    const GOOD_NUMBER: i8 = 42;
-    const BAD_NUMBER: i8 = 0;
+    /// Starts or stops recording which entities were added, moved or evicted each frame.
+    /// This is switched on for indexes whose frames are begun by the systems added with `init_index` and friends;
+    /// standalone indexes have nothing to clear the lists between frames, so they don't record them by default.
+    pub fn set_track_frame_changes(&mut self, track: bool) {
+        self.track_frame_changes = track;
+        if !track {
+            self.clear_frame_changes();
+        }
+    }
 
-    #[derive(Debug, PartialEq, Eq)]
-    enum Goodness {
-        Good,
-        Bad,
-        Confused,
+    /// Entities that moved from one key to another this frame.
+    /// Entities that were newly indexed or evicted are not included.
+    pub fn reassigned_this_frame(&self) -> &[Entity] {
+        &self.reassigned
     }
 
-    fn spawn_bad_entity(commands: &mut Commands) {
-        commands.spawn((MyStruct { val: BAD_NUMBER }, Goodness::Bad));
+    /// Entities that were newly indexed this frame
+    pub fn added_this_frame(&self) -> &[Entity] {
+        &self.added
     }
 
-    fn spawn_good_entity(commands: &mut Commands) {
-        commands.spawn((MyStruct { val: GOOD_NUMBER }, Goodness::Good));
+    /// Entities that were dropped from the index this frame
+    pub fn evicted_this_frame(&self) -> &[Entity] {
+        &self.evicted
     }
 
-    fn spawn_deficient_entity(commands: &mut Commands) {
-        commands.spawn((Goodness::Good,));
+    /// Whether any entity was added, moved or evicted this frame
+    pub fn changed_this_frame(&self) -> bool {
+        self.changed
     }
 
-    fn augment_entities(
-        commands: &mut Commands,
-        query: Query<Entity, (With<Goodness>, Without<MyStruct>)>,
-    ) {
-        for e in query.iter() {
-            commands.insert(e, (MyStruct { val: GOOD_NUMBER },));
-        }
+    /// What the most recent pass of the standard update system did to the index.
+    /// The optional catch-up pass in `LAST` sees the change flags of `POST_UPDATE` again,
+    /// so it only replaces the stats of an earlier pass in the same frame if it changed the index itself.
+    pub fn last_update_stats(&self) -> UpdateStats {
+        self.last_update
     }
 
-    fn reform_entities(
-        mut query: Query<(&mut Goodness, &mut MyStruct)>,
-        index: Res<ComponentIndex<MyStruct>>,
-    ) {
-        let entities = index.get(&MyStruct { val: BAD_NUMBER });
+    // Read-only views of the underlying maps, for queries the methods above don't cover
+    pub fn forward(&self) -> &MultiMap<T, Entity> {
+        &self.forward
+    }
 
-        for e in entities.iter() {
-            let (mut goodness, mut val) = query.get_mut(*e).unwrap();
-            *goodness = Goodness::Good;
-            *val = MyStruct { val: GOOD_NUMBER };
-        }
+    pub fn reverse(&self) -> &HashMap<Entity, T> {
+        &self.reverse
     }
 
-    fn purge_badness(commands: &mut Commands, index: Res<ComponentIndex<MyStruct>>) {
-        let entities = index.get(&MyStruct { val: BAD_NUMBER });
+    /// Every indexed entity along with its key, in arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.reverse.iter().map(|(entity, key)| (*entity, key))
+    }
 
-        for e in entities.iter() {
-            commands.despawn(*e);
+    /// The entities stored under `component_val` that still exist in `world`.
+    /// Entities are stored with their generation, so an entry left behind by a despawned entity
+    /// is skipped even once its id has been recycled for a new entity.
+    pub fn get_live<Q>(&self, component_val: &Q, world: &World) -> Vec<Entity>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        self.entities_of(component_val)
+            .map_or(Vec::new(), |entities| {
+                entities
+                    .iter()
+                    .copied()
+                    .filter(|entity| world.contains(*entity))
+                    .collect()
+            })
+    }
+
+    /// Like `iter`, but skips entities that no longer exist in `world`.
+    /// This gives a clean view even if the index hasn't caught up with despawns yet.
+    pub fn iter_live<'a>(&'a self, world: &'a World) -> impl Iterator<Item = (Entity, &'a T)> {
+        self.iter()
+            .filter(move |(entity, _)| world.contains(*entity))
+    }
+
+    // Looks up the entity list of a key, taking the index's equivalence relation into account.
+    // Only equivalence and normalization need an owned key, so only they build one.
+    fn entities_of<Q>(&self, key: &Q) -> Option<&Vec<Entity>>
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        match self.canonicalize {
+            Some(canonicalize) => self.forward.get_vec(&key.canonical_key(canonicalize)),
+            None => self.forward.get_vec(key),
         }
     }
 
-    fn ensure_goodness(query: Query<&Goodness>, index: Res<ComponentIndex<MyStruct>>) {
-        let entities = index.get(&MyStruct { val: GOOD_NUMBER });
+    // Whether the entity is already stored under the key of component_val
+    fn is_stored_as(&self, entity: Entity, component_val: &T) -> bool {
+        match self.canonicalize {
+            Some(canonicalize) => self.reverse.get(&entity) == Some(&canonicalize(component_val)),
+            None => self.reverse.get(&entity) == Some(component_val),
+        }
+    }
 
-        // Each test must have at least one matching example when checked
-        assert!(entities.len() >= 1);
+    fn remove(&mut self, entity: &Entity) {
+        if self.unlink(entity) {
+            self.record_evicted(*entity);
+            if let Some(log) = self.log.as_mut() {
+                log.push(IndexOp::Remove { entity: *entity });
+            }
+            if let Some(order) = self.insertion_order.as_mut() {
+                order.forget(entity);
+            }
+        }
+    }
 
-        // Each entity with MyStruct.val = GOOD_NUMBER is Good
-        for e in entities.iter() {
-            assert_eq!(
-                query
-                    .get_component::<Goodness>(*e)
-                    .unwrap_or(&Goodness::Confused),
-                &Goodness::Good
-            );
+    // Drops `entity` from both maps, returning whether it was indexed
+    fn unlink(&mut self, entity: &Entity) -> bool {
+        self.expiries.remove(entity);
+        if let Some(old_component) = self.reverse.remove(entity) {
+            self.changed = true;
+            // Only touch the entity list of the key this entity was stored under
+            let n_removed = self.retain_under(&old_component, |e| e != entity);
+
+            // The key's count must drop by exactly one; anything else means the maps had drifted apart
+            if n_removed != 1 {
+                self.report_inconsistency(format!(
+                    "{:?} was not stored under its key when it was removed",
+                    entity
+                ));
+            }
+            true
+        } else {
+            false
         }
     }
 
-    fn ensure_absence_of_bad(query: Query<&Goodness>, index: Res<ComponentIndex<MyStruct>>) {
-        let entities = index.get(&MyStruct { val: BAD_NUMBER });
+    // Keeps only the entities under `key` for which `keep` holds, returning how many were dropped.
+    // The key itself is dropped once empty if the index is set to do so.
+    fn retain_under<F: Fn(&Entity) -> bool>(&mut self, key: &T, keep: F) -> usize {
+        let entities = match self.forward.get_vec_mut(key) {
+            Some(entities) => entities,
+            None => return 0,
+        };
+        let n_before = entities.len();
+        entities.retain(|e| keep(e));
+        let n_removed = n_before - entities.len();
 
-        assert!(entities.len() == 0);
+        if entities.is_empty() && self.drop_empty_keys {
+            if let Some(spare) = self.forward.remove(key) {
+                if self.spare_vecs.len() < self.spare_vecs_limit {
+                    self.spare_vecs.push(spare);
+                }
+            }
+        }
+        n_removed
+    }
 
-        for goodness in query.iter() {
-            assert!(*goodness != Goodness::Bad);
+    /// Evicts every entity in `entities` at once, such as after a mass despawn,
+    /// touching each affected key only once. Returns how many entities were actually indexed.
+    pub fn remove_many<I: IntoIterator<Item = Entity>>(&mut self, entities: I) -> usize {
+        let mut by_key: HashMap<T, HashSet<Entity>> = HashMap::new();
+        for entity in entities {
+            self.expiries.remove(&entity);
+            if let Some(old_component) = self.reverse.remove(&entity) {
+                by_key.entry(old_component).or_default().insert(entity);
+                self.record_evicted(entity);
+                if let Some(log) = self.log.as_mut() {
+                    log.push(IndexOp::Remove { entity });
+                }
+                if let Some(order) = self.insertion_order.as_mut() {
+                    order.forget(&entity);
+                }
+            }
         }
+
+        let mut n_removed = 0;
+        for (key, removed) in by_key.iter() {
+            self.changed = true;
+            n_removed += removed.len();
+            if self.retain_under(key, |e| !removed.contains(e)) != removed.len() {
+                self.report_inconsistency(format!(
+                    "{} entities were removed, but not all of them were stored under their key",
+                    removed.len()
+                ));
+            }
+        }
+        n_removed
     }
 
-    fn debug_index(index: Res<ComponentIndex<MyStruct>>) {
-        dbg!(index);
+    /// Evicts every entity for which `pred` returns false, such as all entities outside of a region.
+    /// Survivors keep their keys and their order within each key.
+    pub fn retain_entities<F: Fn(Entity, &T) -> bool>(&mut self, pred: F) {
+        let doomed: Vec<Entity> = self
+            .reverse
+            .iter()
+            .filter(|(entity, key)| !pred(**entity, key))
+            .map(|(entity, _)| *entity)
+            .collect();
+        self.remove_many(doomed);
     }
 
-    #[test]
-    fn struct_test() {
-        let mut app_builder = App::build();
-        app_builder.init_index::<MyStruct>();
-        app_builder.run();
+    /// Describes the first mismatch found between the forward and reverse maps, if any
+    pub fn find_inconsistency(&self) -> Option<String> {
+        for (entity, key) in self.reverse.iter() {
+            let n_stored = self
+                .forward
+                .get_vec(key)
+                .map_or(0, |e| e.iter().filter(|e| *e == entity).count());
+            if n_stored != 1 {
+                return Some(format!(
+                    "{:?} is stored {} times under its key, rather than once",
+                    entity, n_stored
+                ));
+            }
+        }
+
+        for (key, entities) in self.forward.iter_all() {
+            for entity in entities.iter() {
+                if self.reverse.get(entity) != Some(key) {
+                    return Some(format!(
+                        "{:?} is stored under a key that doesn't match the reverse map",
+                        entity
+                    ));
+                }
+            }
+        }
+
+        None
     }
 
-    #[test]
-    fn tuple_struct_test() {
-        App::build().init_index::<MyTupleStruct>().run()
+    /// Checks that the forward and reverse maps agree, reacting according to the index's `ConsistencyPolicy`
+    pub fn debug_assert_consistent(&self) {
+        if self.consistency_policy == ConsistencyPolicy::Ignore {
+            return;
+        }
+
+        if let Some(inconsistency) = self.find_inconsistency() {
+            self.report_inconsistency(inconsistency);
+        }
     }
-    #[test]
-    fn compound_struct_test() {
-        App::build().init_index::<MyCompoundStruct>().run()
+
+    fn report_inconsistency(&self, inconsistency: String) {
+        match self.consistency_policy {
+            ConsistencyPolicy::Panic => panic!(
+                "Corrupted index of `{}`: {}",
+                type_name::<T>(),
+                inconsistency
+            ),
+            ConsistencyPolicy::Warn => warn!(
+                "Corrupted index of `{}`: {}",
+                type_name::<T>(),
+                inconsistency
+            ),
+            ConsistencyPolicy::Ignore => (),
+        }
     }
-    #[test]
-    fn enum_test() {
-        App::build().init_index::<MyEnum>().run()
+
+    pub fn set_consistency_policy(&mut self, policy: ConsistencyPolicy) {
+        self.consistency_policy = policy;
     }
 
-    #[test]
-    fn startup_spawn_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_startup_system(spawn_good_entity.system())
-            .add_startup_system(spawn_bad_entity.system())
-            .add_system_to_stage(stage::FIRST, ensure_goodness.system())
-            .run()
+    /// Starts or stops logging each insert and removal, to be replayed onto another index with `replay`.
+    /// Only individual operations are logged: bulk rewrites such as `import_state` or `map_keys` are not.
+    /// Stopping discards anything that hasn't been taken yet.
+    pub fn set_recording(&mut self, recording: bool) {
+        self.log = if recording {
+            Some(self.log.take().unwrap_or_default())
+        } else {
+            None
+        };
     }
 
-    #[test]
-    fn update_spawn_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_system(spawn_good_entity.system())
-            .add_system(spawn_bad_entity.system())
-            .add_system_to_stage(stage::LAST, ensure_goodness.system())
-            .run()
+    pub fn is_recording(&self) -> bool {
+        self.log.is_some()
     }
 
-    #[test]
-    fn duplicate_spawn_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_system(spawn_good_entity.system())
-            .add_system(spawn_good_entity.system())
-            .add_system(spawn_bad_entity.system())
-            .add_system_to_stage(stage::LAST, ensure_goodness.system())
-            .run()
+    /// Hands over the operations logged so far, leaving an empty log behind if still recording
+    pub fn take_log(&mut self) -> Vec<IndexOp<T>> {
+        self.log.as_mut().map(std::mem::take).unwrap_or_default()
     }
 
-    #[test]
-    fn component_added_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_startup_system(spawn_deficient_entity.system())
-            .add_startup_system(spawn_bad_entity.system())
-            .add_system(augment_entities.system())
-            .add_system_to_stage(stage::LAST, ensure_goodness.system())
-            .run()
+    /// Starts or stops tracking the order in which entities are first indexed, across all keys,
+    /// for use with `iter_in_insertion_order`.
+    /// Entities that are already indexed when tracking starts come first, sorted by entity.
+    pub fn set_track_insertion_order(&mut self, track: bool) {
+        if !track {
+            self.insertion_order = None;
+        } else if self.insertion_order.is_none() {
+            let mut order = InsertionOrder::default();
+            let mut existing: Vec<Entity> = self.reverse.keys().copied().collect();
+            existing.sort();
+            for entity in existing {
+                order.record(entity);
+            }
+            self.insertion_order = Some(order);
+        }
     }
-    #[test]
-    fn component_modified_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_startup_system(spawn_bad_entity.system())
-            .add_startup_system(spawn_bad_entity.system())
-            .add_system(reform_entities.system())
-            .add_system_to_stage(stage::LAST, ensure_goodness.system())
-            .run()
+
+    /// Every indexed entity, in the order it was first indexed, such as for processing entities in spawn order.
+    /// Moving to another key keeps an entity's place, while being evicted and indexed again sends it to the back.
+    /// Yields nothing unless tracking was enabled with `set_track_insertion_order`.
+    pub fn iter_in_insertion_order(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.insertion_order
+            .iter()
+            .flat_map(|order| order.order.values().copied())
+            // Bulk rewrites such as `import_state` bypass the tracking, so skip anything they dropped
+            .filter(move |entity| self.reverse.contains_key(entity))
     }
 
-    #[test]
-    fn entity_removal_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_startup_system(spawn_bad_entity.system())
-            .add_system(purge_badness.system())
-            .add_system_to_stage(stage::LAST, ensure_absence_of_bad.system())
-            .run()
+    fn begin_frame(&mut self) {
+        self.track_frame_changes = true;
+        self.clear_frame_changes();
+        self.changed = false;
+        self.updated = false;
     }
 
-    #[test]
-    fn duplicate_removal_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_startup_system(spawn_bad_entity.system())
-            .add_startup_system(spawn_bad_entity.system())
-            .add_system(purge_badness.system())
-            .add_system_to_stage(stage::LAST, ensure_absence_of_bad.system())
-            .run()
+    fn clear_frame_changes(&mut self) {
+        self.reassigned.clear();
+        self.reassigned_set.clear();
+        self.added.clear();
+        self.evicted.clear();
     }
 
-    #[test]
-    #[should_panic] // Commands don't get processed until the end of the current Stage
-    fn same_stage_addition_test() {
-        App::build()
-            .init_index::<MyStruct>()
-            .add_system(spawn_deficient_entity.system())
-            .add_system(augment_entities.system())
-            .add_system_to_stage(stage::LAST, ensure_goodness.system())
-            .run()
+    fn record_added(&mut self, entity: Entity) {
+        if self.track_frame_changes {
+            self.added.push(entity);
+        }
+    }
+
+    // Entities that move several times in one frame are only listed once
+    fn record_reassigned(&mut self, entity: Entity) {
+        if self.track_frame_changes && self.reassigned_set.insert(entity) {
+            self.reassigned.push(entity);
+        }
+    }
+
+    fn record_evicted(&mut self, entity: Entity) {
+        if self.track_frame_changes {
+            self.evicted.push(entity);
+        }
+    }
+
+    // Advances the TTL clock, evicting every entry whose time-to-live has run out
+    fn advance_clock(&mut self, delta: f64) {
+        self.clock += delta;
+
+        let now = self.clock;
+        let expired: Vec<Entity> = self
+            .expiries
+            .iter()
+            .filter(|(_, &expiry)| expiry <= now)
+            .map(|(&entity, _)| entity)
+            .collect();
+
+        for entity in expired.iter() {
+            self.remove(entity);
+        }
+    }
+
+    /// Keys that are still stored but no longer have any entities, which `clean` would drop
+    pub fn empty_keys(&self) -> Vec<&T> {
+        self.forward
+            .iter_all()
+            .filter(|(_, entities)| entities.is_empty())
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Drops every key without entities and compacts the remaining entity lists
+    pub fn clean(&mut self) {
+        let old_forward = std::mem::replace(&mut self.forward, MultiMap::new());
+
+        for (key, mut entities) in old_forward.into_iter() {
+            if !entities.is_empty() {
+                entities.shrink_to_fit();
+                self.forward.insert_many(key, entities);
+            }
+        }
+        self.reverse.shrink_to_fit();
+    }
+
+    /// Releases the spare capacity of both maps and their entity lists, including the lists kept for reuse.
+    /// Call `clean` first to drop empty keys as well.
+    pub fn shrink_to_fit(&mut self) {
+        for (_, entities) in self.forward.iter_all_mut() {
+            entities.shrink_to_fit();
+        }
+        self.forward.shrink_to_fit();
+        self.reverse.shrink_to_fit();
+        self.spare_vecs = Vec::new();
+    }
+
+    /// A rough estimate of the heap memory held by the index, from the capacities of its maps and entity lists.
+    /// Only the keys themselves are counted, not any heap data they own, such as the contents of a `String`.
+    pub fn estimated_memory_bytes(&self) -> usize {
+        let entity_size = std::mem::size_of::<Entity>();
+        let key_size = std::mem::size_of::<T>();
+
+        let forward_table =
+            self.forward.capacity() * (key_size + std::mem::size_of::<Vec<Entity>>());
+        let entity_lists: usize = self
+            .forward
+            .iter_all()
+            .map(|(_, entities)| entities.capacity() * entity_size)
+            .sum();
+        let reverse_table = self.reverse.capacity() * (entity_size + key_size);
+        let spare_lists: usize = self
+            .spare_vecs
+            .iter()
+            .map(|entities| entities.capacity() * entity_size)
+            .sum();
+
+        forward_table + entity_lists + reverse_table + spare_lists
+    }
+
+    /// When enabled, keys are dropped as soon as their last entity leaves,
+    /// so indexes over high-cardinality, short-lived keys don't grow without bound.
+    /// `lookup` will then report emptied keys as `Lookup::Missing`.
+    pub fn set_drop_empty_keys(&mut self, drop_empty_keys: bool) {
+        self.drop_empty_keys = drop_empty_keys;
+        if drop_empty_keys {
+            self.clean();
+        }
+    }
+
+    /// Sets how many entity lists of dropped keys are kept for reuse by new keys, 64 by default.
+    /// Raising this cuts down on allocations when keys churn while `set_drop_empty_keys` is enabled.
+    pub fn set_spare_vecs_limit(&mut self, limit: usize) {
+        self.spare_vecs_limit = limit;
+        self.spare_vecs.truncate(limit);
+    }
+
+    /// Registers a check run on each key before the update systems index it, such as rejecting out-of-bounds positions.
+    /// Entities whose key fails the check are logged and left out of the index.
+    pub fn set_validation(&mut self, validator: fn(&T) -> bool) {
+        self.validator = Some(validator);
+    }
+
+    // Runs the validation callback on `key`, evicting `entity` if its new key is rejected
+    fn validate(&mut self, entity: Entity, key: &T) -> bool {
+        match self.validator {
+            Some(validator) if !validator(key) => {
+                warn!("{:?} was not indexed, as its key failed validation", entity);
+                self.remove(&entity);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether `key` can be stored and found again.
+    /// Keys that aren't equal to themselves, such as wrappers around a NaN float, can never be looked up.
+    pub fn is_valid_key(key: &T) -> bool {
+        PartialEq::eq(key, key)
+    }
+
+    // TODO: add manual_update function for multi-stage flow
+}
+
+impl<T: Hash + Eq + Clone> ComponentIndex<T> {
+    pub fn insert(&mut self, component: T, entity: Entity) {
+        let component = match self.canonicalize {
+            Some(canonicalize) => canonicalize(&component),
+            None => component,
+        };
+        debug_assert!(
+            Self::is_valid_key(&component),
+            "Index keys must be equal to themselves, but this one isn't (is it wrapping a NaN float?). \
+            Use a FuzzyIndex to index by floating point values instead."
+        );
+        let moved = matches!(self.reverse.get(&entity), Some(old) if *old != component);
+        if moved {
+            self.record_reassigned(entity);
+        }
+        // Reinserting an entity under the key it already has doesn't count as a change
+        let changed = self.changed || self.reverse.get(&entity) != Some(&component);
+        if !self.reverse.contains_key(&entity) {
+            self.record_added(entity);
+            if let Some(order) = self.insertion_order.as_mut() {
+                order.record(entity);
+            }
+        }
+        if let Some(log) = self.log.as_mut() {
+            log.push(match self.reverse.get(&entity) {
+                Some(old) => IndexOp::Reassign {
+                    entity,
+                    from: old.clone(),
+                    to: component.clone(),
+                },
+                None => IndexOp::Insert {
+                    entity,
+                    key: component.clone(),
+                },
+            });
+        }
+
+        self.unlink(&entity);
+        if let Some(ttl) = self.default_ttl {
+            self.expiries.insert(entity, self.clock + ttl);
+        }
+        match self.forward.entry(component.clone()) {
+            Entry::Occupied(mut entry) => entry.get_vec_mut().push(entity),
+            Entry::Vacant(entry) => {
+                let mut entities = self.spare_vecs.pop().unwrap_or_default();
+                entities.push(entity);
+                entry.insert_vec(entities);
+            }
+        }
+        self.reverse.insert(entity, component);
+        self.changed = changed;
+    }
+
+    /// Stores every entity in `entities` under `key` at once, such as a formation spawned onto one tile.
+    /// Entities stored under other keys are moved, touching each of their old keys only once,
+    /// and end up in the same order as if they had been inserted one by one.
+    pub fn insert_batch(&mut self, key: T, entities: &[Entity]) {
+        if entities.is_empty() {
+            return;
+        }
+        let key = match self.canonicalize {
+            Some(canonicalize) => canonicalize(&key),
+            None => key,
+        };
+        debug_assert!(
+            Self::is_valid_key(&key),
+            "Index keys must be equal to themselves, but this one isn't (is it wrapping a NaN float?)"
+        );
+        let mut seen = HashSet::with_capacity(entities.len());
+        let entities: Vec<Entity> = entities
+            .iter()
+            .copied()
+            .filter(|e| seen.insert(*e))
+            .collect();
+
+        // Entities already stored under `key` are pulled out too, so that they move to the back like with `insert`
+        let mut by_old_key: HashMap<T, HashSet<Entity>> = HashMap::new();
+        for entity in entities.iter() {
+            if let Some(old_key) = self.reverse.get(entity) {
+                by_old_key
+                    .entry(old_key.clone())
+                    .or_default()
+                    .insert(*entity);
+            }
+        }
+        for (old_key, moved) in by_old_key.iter() {
+            if self.retain_under(old_key, |e| !moved.contains(e)) != moved.len() {
+                self.report_inconsistency(format!(
+                    "{} entities were moved, but not all of them were stored under their key",
+                    moved.len()
+                ));
+            }
+        }
+
+        for &entity in entities.iter() {
+            match self.reverse.insert(entity, key.clone()) {
+                Some(old_key) => {
+                    if old_key != key {
+                        self.changed = true;
+                        self.record_reassigned(entity);
+                    }
+                    if let Some(log) = self.log.as_mut() {
+                        log.push(IndexOp::Reassign {
+                            entity,
+                            from: old_key,
+                            to: key.clone(),
+                        });
+                    }
+                }
+                None => {
+                    self.changed = true;
+                    self.record_added(entity);
+                    if let Some(order) = self.insertion_order.as_mut() {
+                        order.record(entity);
+                    }
+                    if let Some(log) = self.log.as_mut() {
+                        log.push(IndexOp::Insert {
+                            entity,
+                            key: key.clone(),
+                        });
+                    }
+                }
+            }
+            match self.default_ttl {
+                Some(ttl) => self.expiries.insert(entity, self.clock + ttl),
+                None => self.expiries.remove(&entity),
+            };
+        }
+
+        match self.forward.entry(key) {
+            Entry::Occupied(mut entry) => entry.get_vec_mut().extend(entities),
+            Entry::Vacant(entry) => {
+                let mut spare = self.spare_vecs.pop().unwrap_or_default();
+                spare.extend(entities);
+                entry.insert_vec(spare);
+            }
+        }
+    }
+
+    /// Gets the entry for `key`, creating the key if it isn't present yet.
+    /// Unlike `HashMap::entry`, entities must be added through `IndexEntry::push`,
+    /// so that the reverse map stays consistent.
+    pub fn entry(&mut self, key: T) -> IndexEntry<'_, T> {
+        let key = match self.canonicalize {
+            Some(canonicalize) => canonicalize(&key),
+            None => key,
+        };
+        self.forward.entry(key.clone()).or_insert_vec(Vec::new());
+
+        IndexEntry { index: self, key }
+    }
+
+    /// Rebuilds the index from scratch out of every entity matched by `query`,
+    /// for when the index should be built at a specific moment, such as after a level loads.
+    /// The old entries are removed one by one, so recording and insertion order tracking see the rebuild.
+    pub fn populate_from(&mut self, query: &Query<(&T, Entity)>)
+    where
+        T: Component,
+    {
+        let indexed: Vec<Entity> = self.reverse.keys().copied().collect();
+        self.remove_many(indexed);
+
+        for (component, entity) in query.iter() {
+            self.insert(component.clone(), entity);
+        }
+    }
+
+    /// Applies `normalize` to every key before it is stored or looked up,
+    /// such as clamping positions to the bounds of the map.
+    /// Keys that are already stored are normalized immediately.
+    pub fn set_normalization(&mut self, normalize: fn(&T) -> T) {
+        self.canonicalize = Some(normalize);
+        self.map_keys(normalize);
+    }
+
+    /// Swaps in the contents of `other`, such as an index built off-thread, in a single step.
+    /// The configuration of this index is kept, and its own maps are updated in place to reuse their allocations.
+    /// Only the entries that differ are touched, so they are recorded as evictions, moves and insertions
+    /// like any other change. Each key then lists its entities in the same order as in `other`.
+    pub fn replace_with(&mut self, other: ComponentIndex<T>) {
+        let stale: Vec<Entity> = self
+            .reverse
+            .keys()
+            .filter(|entity| !other.reverse.contains_key(entity))
+            .copied()
+            .collect();
+        self.remove_many(stale);
+
+        for (key, entities) in other.forward.iter_all() {
+            for &entity in entities {
+                if !self.is_stored_as(entity, key) {
+                    self.insert(key.clone(), entity);
+                }
+            }
+        }
+
+        // Entities that kept their key also kept their place, so each list is rewritten in the order of `other`.
+        // Keys that only differ before normalization share a list, which is only cleared once.
+        let mut rewritten = HashSet::new();
+        for (key, entities) in other.forward.iter_all() {
+            let key = match self.canonicalize {
+                Some(canonicalize) => canonicalize(key),
+                None => key.clone(),
+            };
+            if let Some(list) = self.forward.get_vec_mut(&key) {
+                if rewritten.insert(key) {
+                    list.clear();
+                }
+                list.extend(entities.iter().copied());
+            }
+        }
+
+        // Expiry times are relative to each index's own clock
+        let offset = self.clock - other.clock;
+        self.expiries.clear();
+        self.expiries.extend(
+            other
+                .expiries
+                .into_iter()
+                .map(|(entity, expiry)| (entity, expiry + offset)),
+        );
+    }
+
+    /// Merges the entries of `other` into this index, such as indexes built separately for each region.
+    /// Entities present in both end up under their key from `other`.
+    pub fn extend(&mut self, other: ComponentIndex<T>) {
+        let offset = self.clock - other.clock;
+        let mut other_expiries = other.expiries;
+
+        for (key, entities) in other.forward.into_iter() {
+            for entity in entities {
+                self.insert(key.clone(), entity);
+                if let Some(expiry) = other_expiries.remove(&entity) {
+                    self.expiries.insert(entity, expiry + offset);
+                }
+            }
+        }
+    }
+
+    /// Splits the index in two by its keys: entries whose key satisfies `predicate` go to the first index,
+    /// and the rest to the second, such as positions on the left and right halves of the map.
+    /// Both halves keep this index's configuration and time-to-live bookkeeping.
+    pub fn partition<F: Fn(&T) -> bool>(
+        self,
+        predicate: F,
+    ) -> (ComponentIndex<T>, ComponentIndex<T>) {
+        let mut matching = self.empty_like();
+        let mut rest = self.empty_like();
+        let mut expiries = self.expiries;
+
+        for (key, entities) in self.forward.into_iter() {
+            let half = if predicate(&key) {
+                &mut matching
+            } else {
+                &mut rest
+            };
+            for entity in entities.iter() {
+                half.reverse.insert(*entity, key.clone());
+                if let Some(expiry) = expiries.remove(entity) {
+                    half.expiries.insert(*entity, expiry);
+                }
+            }
+            half.forward.insert_many(key, entities);
+        }
+
+        (matching, rest)
+    }
+
+    // An empty index with the same configuration as this one
+    fn empty_like(&self) -> ComponentIndex<T> {
+        ComponentIndex::<T> {
+            clock: self.clock,
+            default_ttl: self.default_ttl,
+            canonicalize: self.canonicalize,
+            validator: self.validator,
+            filter: self.filter,
+            coalesce_changes: self.coalesce_changes,
+            consistency_policy: self.consistency_policy,
+            drop_empty_keys: self.drop_empty_keys,
+            spare_vecs_limit: self.spare_vecs_limit,
+            ..ComponentIndex::default()
+        }
+    }
+
+    /// Replaces every key by `f(key)`, merging the entities of keys that end up colliding.
+    /// Merged entities are ordered by when they were first indexed if insertion order is tracked.
+    /// Otherwise the entity lists of the colliding keys are concatenated whole, keeping the order within each,
+    /// and ordered by their first entity so that `get` stays deterministic.
+    pub fn map_keys<F: Fn(&T) -> T>(&mut self, f: F) {
+        let old_forward = std::mem::replace(&mut self.forward, MultiMap::new());
+        let mut regrouped: HashMap<T, Vec<Vec<Entity>>> = HashMap::new();
+
+        for (old_key, entities) in old_forward.into_iter() {
+            let new_key = f(&old_key);
+            for entity in entities.iter() {
+                self.reverse.insert(*entity, new_key.clone());
+            }
+            regrouped.entry(new_key).or_default().push(entities);
+        }
+
+        for (key, mut groups) in regrouped.into_iter() {
+            let entities = match (groups.len(), self.insertion_order.as_ref()) {
+                (1, _) => groups.pop().unwrap_or_default(),
+                (_, Some(order)) => {
+                    let mut merged = groups.concat();
+                    merged.sort_by_key(|entity| order.sequence_of.get(entity).copied());
+                    merged
+                }
+                (_, None) => {
+                    groups.sort_by_key(|entities| entities.first().copied());
+                    groups.concat()
+                }
+            };
+            self.forward.insert_many(key, entities);
+        }
+    }
+
+    /// Copies the contents of the index into a plain struct that can be persisted and imported later.
+    /// Time-to-live bookkeeping is not included.
+    pub fn export_state(&self) -> IndexState<T> {
+        IndexState {
+            entries: self
+                .forward
+                .iter_all()
+                .map(|(key, entities)| (key.clone(), entities.clone()))
+                .collect(),
+        }
+    }
+
+    /// Replaces the contents of the index with a previously exported state
+    pub fn import_state(&mut self, state: IndexState<T>) {
+        self.forward = MultiMap::new();
+        self.reverse = HashMap::new();
+        self.expiries = HashMap::new();
+
+        for (key, entities) in state.entries.into_iter() {
+            for entity in entities.iter() {
+                self.reverse.insert(*entity, key.clone());
+            }
+            self.forward.insert_many(key, entities);
+        }
+    }
+
+    /// Applies a log taken with `take_log`, reproducing the operations that built the original index
+    pub fn replay(&mut self, log: &[IndexOp<T>]) {
+        for op in log.iter() {
+            match op {
+                IndexOp::Insert { entity, key } => self.insert(key.clone(), *entity),
+                IndexOp::Reassign { entity, to, .. } => self.insert(to.clone(), *entity),
+                IndexOp::Remove { entity } => self.remove(entity),
+            }
+        }
+    }
+
+    /// The changes that turn `older`, such as a stale copy of this index, into this index.
+    /// Entities are listed in id order, so equal indexes always produce equal diffs.
+    pub fn diff(&self, older: &ComponentIndex<T>) -> IndexDiff<T> {
+        let mut diff = IndexDiff {
+            added: Vec::new(),
+            reassigned: Vec::new(),
+            removed: Vec::new(),
+        };
+        for (entity, key) in self.reverse.iter() {
+            match older.reverse.get(entity) {
+                None => diff.added.push((*entity, key.clone())),
+                Some(old_key) if old_key != key => {
+                    diff.reassigned
+                        .push((*entity, old_key.clone(), key.clone()))
+                }
+                Some(_) => (),
+            }
+        }
+        for (entity, old_key) in older.reverse.iter() {
+            if !self.reverse.contains_key(entity) {
+                diff.removed.push((*entity, old_key.clone()));
+            }
+        }
+
+        diff.added.sort_by_key(|(entity, _)| *entity);
+        diff.reassigned.sort_by_key(|(entity, _, _)| *entity);
+        diff.removed.sort_by_key(|(entity, _)| *entity);
+        diff
+    }
+
+    /// Applies a diff produced by `diff`, such as one received from a server, to bring this index in sync.
+    /// Changes that were already applied are skipped silently,
+    /// while changes that conflict with the current contents of the index are logged and skipped.
+    pub fn apply_delta(&mut self, delta: IndexDiff<T>) {
+        for (entity, old_key) in delta.removed.into_iter() {
+            match self.reverse.get(&entity) {
+                None => (),
+                Some(key) if *key == old_key => self.remove(&entity),
+                Some(_) => warn!(
+                    "Skipped removing {:?}, which is stored under a different key than expected",
+                    entity
+                ),
+            }
+        }
+
+        for (entity, from, to) in delta.reassigned.into_iter() {
+            match self.reverse.get(&entity) {
+                Some(key) if *key == to => (),
+                Some(key) if *key == from => self.insert(to, entity),
+                _ => warn!(
+                    "Skipped moving {:?}, which is not stored under the key it was moved from",
+                    entity
+                ),
+            }
+        }
+
+        for (entity, key) in delta.added.into_iter() {
+            match self.reverse.get(&entity) {
+                None => self.insert(key, entity),
+                Some(current) if *current == key => (),
+                Some(_) => warn!(
+                    "Skipped adding {:?}, which is already stored under another key",
+                    entity
+                ),
+            }
+        }
+    }
+
+    /// Inserts an entry that is evicted once `ttl` units of the index's `TtlClock` have passed,
+    /// unless it is inserted again before then
+    pub fn insert_with_ttl(&mut self, component: T, entity: Entity, ttl: f64) {
+        self.insert(component, entity);
+        self.expiries.insert(entity, self.clock + ttl);
+    }
+
+    /// A view of the index that caches the entities of each key it looks up
+    pub fn cached(&self) -> CachedIndex<'_, T> {
+        CachedIndex::new(self)
+    }
+
+    /// Entities stored under exactly one of `key_a` and `key_b`.
+    /// Each entity only has one key, so this is every entity under either key, unless the keys are equal.
+    pub fn symmetric_difference(&self, key_a: &T, key_b: &T) -> Vec<Entity> {
+        symmetric_difference(&self.get(key_a), &self.get(key_b))
+    }
+
+    /// A copy of the entries of the index, to compare against later with `key_delta`.
+    /// Key normalization is kept, but time-to-live bookkeeping and other options are not.
+    pub fn snapshot(&self) -> ComponentIndex<T> {
+        ComponentIndex::<T> {
+            forward: self.forward.clone(),
+            reverse: self.reverse.clone(),
+            canonicalize: self.canonicalize,
+            ..ComponentIndex::default()
+        }
+    }
+
+    /// The entities that joined and left `key` since `snapshot` was taken, in that order.
+    /// Entities that left and came back in the meantime appear in neither list.
+    pub fn key_delta<Q>(&self, snapshot: &ComponentIndex<T>, key: &Q) -> (Vec<Entity>, Vec<Entity>)
+    where
+        T: Borrow<Q>,
+        Q: ?Sized + LookupKey<T>,
+    {
+        let now: &[Entity] = self.entities_of(key).map_or(&[], |e| e);
+        let then: &[Entity] = snapshot.entities_of(key).map_or(&[], |e| e);
+        let now_set: HashSet<&Entity> = now.iter().collect();
+        let then_set: HashSet<&Entity> = then.iter().collect();
+
+        let joined = now.iter().filter(|e| !then_set.contains(e)).copied();
+        let left = then.iter().filter(|e| !now_set.contains(e)).copied();
+        (joined.collect(), left.collect())
+    }
+
+    /// All entities tagged with the current value of the `State<T>` resource
+    pub fn in_current_state(&self, state: &State<T>) -> Cow<'_, [Entity]> {
+        self.get(state.current())
+    }
+}
+
+impl<T: Hash + Eq + Debug> ComponentIndex<T> {
+    /// Lists each non-empty key with its number of entities, one per line, sorted for stable output
+    pub fn dump(&self) -> String {
+        let mut lines: Vec<String> = self
+            .forward
+            .iter_all()
+            .filter(|(_, entities)| !entities.is_empty())
+            .map(|(key, entities)| format!("{:?}: {}", key, entities.len()))
+            .collect();
+        lines.sort();
+
+        lines.join("\n")
+    }
+}
+
+impl<T: Hash + Eq> Default for ComponentIndex<T> {
+    fn default() -> Self {
+        ComponentIndex::<T> {
+            forward: MultiMap::new(),
+            reverse: HashMap::new(),
+            clock: 0.0,
+            default_ttl: None,
+            expiries: HashMap::new(),
+            canonicalize: None,
+            validator: None,
+            filter: None,
+            coalesce_changes: false,
+            consistency_policy: ConsistencyPolicy::default(),
+            drop_empty_keys: false,
+            spare_vecs: Vec::new(),
+            spare_vecs_limit: 64,
+            paused: false,
+            resync_pending: false,
+            log: None,
+            insertion_order: None,
+            track_frame_changes: false,
+            reassigned: Vec::new(),
+            reassigned_set: HashSet::new(),
+            added: Vec::new(),
+            evicted: Vec::new(),
+            last_update: UpdateStats::default(),
+            changed: false,
+            updated: false,
+        }
+    }
+}
+
+// Two indexes are equal when they store the same entities under the same keys
+impl<T: Hash + Eq> PartialEq for ComponentIndex<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.forward == other.forward && self.reverse == other.reverse
+    }
+}
+
+impl<T: Hash + Eq> Eq for ComponentIndex<T> {}
+
+/// The work done by one pass of the update system of a `ComponentIndex`, see `ComponentIndex::last_update_stats`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateStats {
+    /// Entities that weren't indexed before
+    pub inserted: usize,
+    /// Entities that were evicted, because their component was removed or their new key was rejected
+    pub removed: usize,
+    /// Entities that moved to a different key
+    pub reassigned: usize,
+    /// Entities flagged as changed whose key was already stored, or whose rejected key left nothing to evict
+    pub skipped: usize,
+}
+
+impl UpdateStats {
+    fn changed_index(&self) -> bool {
+        self.inserted + self.removed + self.reassigned > 0
+    }
+}
+
+// The order in which entities were first indexed, as sequence numbers so that removals stay cheap
+#[derive(Debug, Default)]
+struct InsertionOrder {
+    next: u64,
+    sequence_of: HashMap<Entity, u64>,
+    order: BTreeMap<u64, Entity>,
+}
+
+impl InsertionOrder {
+    fn record(&mut self, entity: Entity) {
+        // An entity can be left behind by a bulk rewrite, in which case its old place is dropped
+        if let Some(old) = self.sequence_of.insert(entity, self.next) {
+            self.order.remove(&old);
+        }
+        self.order.insert(self.next, entity);
+        self.next += 1;
+    }
+
+    fn forget(&mut self, entity: &Entity) {
+        if let Some(sequence) = self.sequence_of.remove(entity) {
+            self.order.remove(&sequence);
+        }
+    }
+}
+
+/// A single key of a `ComponentIndex`, as returned by `ComponentIndex::entry`
+pub struct IndexEntry<'a, T: Hash + Eq> {
+    index: &'a mut ComponentIndex<T>,
+    key: T,
+}
+
+impl<'a, T: Hash + Eq + Clone> IndexEntry<'a, T> {
+    /// Stores `entity` under this entry's key, moving it out of any other key it was stored under
+    pub fn push(&mut self, entity: Entity) -> &mut Self {
+        self.index.insert(self.key.clone(), entity);
+        self
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        match self.index.forward.get_vec(&self.key) {
+            Some(e) => e,
+            None => &[],
+        }
+    }
+}
+
+/// A serialization-agnostic snapshot of a `ComponentIndex<T>`,
+/// as returned by `export_state`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexState<T> {
+    /// Each key with its entities, in the order they were stored
+    pub entries: Vec<(T, Vec<Entity>)>,
+}
+
+/// The entities that were added, reassigned and removed between two versions of a `ComponentIndex<T>`,
+/// as returned by `diff` and applied with `apply_delta`.
+/// Old keys are included so that deltas applied out of order can be detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexDiff<T> {
+    pub added: Vec<(Entity, T)>,
+    /// Each moved entity, with the key it moved from and the key it moved to
+    pub reassigned: Vec<(Entity, T, T)>,
+    pub removed: Vec<(Entity, T)>,
+}
+
+/// A single operation recorded by a `ComponentIndex` while recording, see `ComponentIndex::set_recording`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IndexOp<T> {
+    /// An entity that wasn't indexed was stored under `key`
+    Insert {
+        entity: Entity,
+        key: T,
+    },
+    /// An indexed entity was stored again, moving it from `from` to `to`
+    Reassign {
+        entity: Entity,
+        from: T,
+        to: T,
+    },
+    Remove {
+        entity: Entity,
+    },
+}
+
+/// The result of `ComponentIndex::lookup`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Lookup<'a> {
+    /// The key has never been indexed
+    Missing,
+    /// The key was indexed at some point, but no entities currently have it
+    Empty,
+    Entities(&'a [Entity]),
+}
+
+/// How an index reacts when `debug_assert_consistent` detects corruption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsistencyPolicy {
+    /// Panics with a description of the corruption
+    Panic,
+    /// Logs a warning and carries on
+    Warn,
+    /// Skips the check entirely
+    Ignore,
+}
+
+impl Default for ConsistencyPolicy {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            ConsistencyPolicy::Warn
+        } else {
+            ConsistencyPolicy::Ignore
+        }
+    }
+}
+
+/// The clock source used to expire the entries of an index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtlClock {
+    /// Time-to-live is counted in frames
+    Frames,
+    /// Time-to-live is counted in seconds, as measured by the `Time` resource
+    Seconds,
+}
+
+pub trait ComponentIndexes {
+    /// Initializes and maintains a `ComponentIndex<T>` with the default options.
+    /// Use an `IndexBuilder` to configure the index further.
+    /// Components inserted by commands flushed at the end of `POST_UPDATE`, such as by the scene spawner,
+    /// are only picked up if the index catches up in `LAST`, see `IndexBuilder::catch_up_in_last`.
+    /// Despawned entities are evicted on the next update,
+    /// including every descendant of an entity removed with `despawn_recursive`.
+    fn init_index<T: IndexKey>(&mut self) -> &mut Self;
+
+    /// Initializes an index that is only updated on the ticks of the `IndexTimestep` resource,
+    /// in the `FIXED_INDEX_UPDATE` stage, for simulations that only change their components on ticks.
+    /// Reads between ticks see the index as of the last tick.
+    /// Change detection is reset every frame, so each tick compares the index against the whole world,
+    /// catching up on the changes and removals made since the last one.
+    /// Add an `IndexTimestep` before calling this to pick the step; it defaults to 60 ticks per second.
+    fn init_index_fixed<T: IndexKey>(&mut self) -> &mut Self;
+
+    /// Initializes an index that only holds entities matching the query filter `F`,
+    /// such as `Or<(With<A>, Without<B>)>`.
+    /// Only entities that gained or lost one of the filter's components are rechecked against it,
+    /// see `IndexFilter` for the filters this supports.
+    fn init_index_with_filter<T: IndexKey, F: IndexFilter>(&mut self) -> &mut Self;
+
+    /// Reports the changes made to the `ComponentIndex<T>` each frame as events, as configured by `mode`.
+    /// The index must already be initialized.
+    fn init_index_change_events<T: IndexKey>(&mut self, mode: ChangeEventMode) -> &mut Self;
+
+    /// Sends a `KeyOccupancyChanged<T>` event from `LAST` whenever a key of the `ComponentIndex<T>`
+    /// gains its first entity or loses its last one. The index must already be initialized.
+    fn init_key_occupancy_events<T: IndexKey>(&mut self) -> &mut Self;
+
+    /// Runs `watcher` at the end of every frame where at least one key of the `ComponentIndex<T>`
+    /// gained its first entity or lost its last one, such as to redraw every tile that was filled or emptied.
+    /// The watcher reads the affected keys from the `WatchedKeys<T>` resource,
+    /// which are taken from the `IndexBatchChanged<T>` events that this enables.
+    /// Every watcher of `T` shares a stage placed after `LAST`. The index must already be initialized.
+    fn add_index_watcher<T: IndexKey, S: System<In = (), Out = ()>>(
+        &mut self,
+        watcher: S,
+    ) -> &mut Self;
+
+    /// Pushes every operation applied to the `ComponentIndex<T>` to `sink`, once per frame from `LAST`,
+    /// such as to mirror the index into a spatial acceleration structure.
+    /// Sinks drain the operation log of the index, so `take_log` is left empty.
+    /// The index must already be initialized.
+    fn add_index_sink<T: IndexKey>(&mut self, sink: IndexSink<T>) -> &mut Self;
+
+    /// Indexes each `C` under every key returned by `projection`
+    fn init_multi_key_index<C: Component, K: Eq + Hash + Clone + Send + Sync + 'static>(
+        &mut self,
+        projection: fn(&C) -> Vec<K>,
+    ) -> &mut Self;
+
+    /// Initializes an index whose entries are evicted after `default_ttl` units of `clock`,
+    /// unless the component changes in the meantime
+    fn init_index_with_ttl<T: IndexKey>(&mut self, clock: TtlClock, default_ttl: f64) -> &mut Self;
+
+    /// Initializes an index that is driven by the events `E` instead of change detection.
+    /// `E` must already be registered with `add_event`.
+    fn init_index_from_events<E: IndexEvent<T>, T: IndexKey>(&mut self) -> &mut Self;
+
+    /// Indexes each `C` by the bucket of size `cell_size` that `projection` places it in
+    fn init_fuzzy_index<C: Component>(
+        &mut self,
+        cell_size: f32,
+        projection: fn(&C) -> f32,
+    ) -> &mut Self;
+
+    /// Indexes each `C` by the window of `bucket_size` seconds that the timestamp returned by `project` falls into.
+    /// Timestamps are compared against the index's clock, which follows the `Time` resource,
+    /// so should be measured in seconds since startup.
+    fn init_time_bucket_index<C: Component>(
+        &mut self,
+        bucket_size: f64,
+        project: fn(&C) -> f64,
+    ) -> &mut Self;
+
+    /// Indexes each `C` under the category `K` returned by `categorize`, typically a trait method.
+    /// Several component types can feed the same `ComponentIndex<K>`, sorting them into shared buckets.
+    /// An entity with more than one of those components is stored under the category of whichever changed last.
+    fn init_index_category<C: Component, K: IndexKey>(
+        &mut self,
+        categorize: fn(&C) -> K,
+    ) -> &mut Self;
+
+    /// Indexes each `C` under the key returned by `project`, which can borrow the key from `C` itself.
+    /// Keys are only cloned when an entity is stored under a new key,
+    /// while derived keys can still be computed as `Cow::Owned`.
+    fn init_borrowed_projection<C: Component, K: IndexKey>(
+        &mut self,
+        project: for<'a> fn(&'a C) -> Cow<'a, K>,
+    ) -> &mut Self;
+
+    /// Indexes each entity with a `Parent` under the key that `project` computes from its parent's `C`,
+    /// such as the room that an item has been placed in.
+    /// Children are reindexed when they are reparented or when their parent's `C` changes.
+    fn init_parent_projection<C: Component, K: IndexKey>(
+        &mut self,
+        project: fn(&C) -> K,
+    ) -> &mut Self;
+
+    /// Indexes each `C` by its `Display` output, so entities can be looked up by the string they're shown as
+    fn init_display_index<C: Component + Display>(&mut self) -> &mut Self;
+
+    /// Initializes an index whose keys are stored and looked up by their `IndexEquiv::canonical` form
+    fn init_equiv_index<T: IndexEquiv>(&mut self) -> &mut Self;
+
+    /// Initializes a `CaseInsensitiveStringIndex`, which folds the case of its keys before storing or looking them up
+    fn init_case_insensitive_index(&mut self) -> &mut Self;
+
+    /// Initializes an index whose keys are passed through `normalize` before being stored or looked up
+    fn init_normalized_index<T: IndexKey>(&mut self, normalize: fn(&T) -> T) -> &mut Self;
+
+    /// Initializes a `GridIndex<C>`, which stores each `C` in the cell of `topology` returned by `projection`
+    /// and can look up the entities around a cell, wrapping around the edges of toroidal grids
+    fn init_grid_index<C: Component>(
+        &mut self,
+        topology: GridTopology,
+        projection: fn(&C) -> GridCell,
+    ) -> &mut Self;
+
+    /// Initializes a `ProjectionIndexes<C>`, which feeds several indexes from a single pass over `C`.
+    /// Add an index for each projection with `MultiProjectionBuilder::with`.
+    fn init_multi_projection<C: Component>(&mut self) -> MultiProjectionBuilder<'_, C>;
+
+    /// Indexes entities that have both an `A` and a `B` under the key `(A, B)`
+    fn init_index_tuple<A: IndexKey, B: IndexKey>(&mut self) -> &mut Self;
+
+    /// Indexes Bevy's built-in `Name` component, for looking up entities by name
+    fn init_name_index(&mut self) -> &mut Self;
+
+    /// Initializes an `LruIndex<T>`, which only retains the `capacity` most recently updated entities
+    fn init_lru_index<T: IndexKey>(&mut self, capacity: usize) -> &mut Self;
+
+    /// Indexes entities by the `Handle<A>` they hold, for finding every entity that uses a given asset
+    fn init_handle_index<A: Asset>(&mut self) -> &mut Self;
+
+    /// Indexes entities by a component holding the same type as a `State<S>` resource,
+    /// such as the game phase they belong to.
+    /// Use `ComponentIndex::in_current_state` to fetch the entities tagged with the active state.
+    fn init_state_index<S: IndexKey>(&mut self) -> &mut Self;
+
+    /// Initializes an `AdjacencyIndex`, which tracks the neighbors of entities linked by `Edge` components
+    fn init_adjacency_index(&mut self) -> &mut Self;
+
+    /// Initializes a `MembershipIndex<C>`, which maps each entity listed in a `C` back to its owner
+    fn init_membership_index<C: EntityList>(&mut self) -> &mut Self;
+
+    /// Initializes a `TagIndex<T>`, the set of entities that have the marker component `T`
+    fn init_tag_index<T: Component>(&mut self) -> &mut Self;
+
+    /// Initializes a `ShardedIndex<T>` split into `n_shards` shards,
+    /// which are updated in parallel on the `ComputeTaskPool`
+    fn init_sharded_index<T: IndexKey>(&mut self, n_shards: usize) -> &mut Self;
+
+    /// Initializes a `SparseIndex<T>`, which avoids allocating for keys held by a single entity
+    fn init_sparse_index<T: IndexKey>(&mut self) -> &mut Self;
+
+    /// Initializes a `RangeIndex<T>`, which supports range queries over ordered keys
+    fn init_range_index<T: Component + Ord + Clone>(&mut self) -> &mut Self;
+
+    /// Indexes a key type that is `Ord` but not `Hash`.
+    /// The index is a `RangeIndex<T>`, which is backed entirely by a `BTreeMap`.
+    fn init_ord_index<T: Component + Ord + Clone>(&mut self) -> &mut Self;
+
+    fn update_component_index<T: IndexKey>(
+        index: ResMut<ComponentIndex<T>>,
+        metrics: ResMut<IndexMetrics<T>>,
+        query: Query<(&T, Entity)>,
+        changed_query: Query<(&T, Entity), Changed<T>>,
+    );
+}
+
+impl ComponentIndexes for AppBuilder {
+    fn init_index<T: IndexKey>(&mut self) -> &mut Self {
+        IndexBuilder::<T>::new().build(self);
+
+        self
+    }
+
+    fn init_index_fixed<T: IndexKey>(&mut self) -> &mut Self {
+        // The stage is shared by every fixed index, so only the first one adds it
+        self.init_resource::<IndexTimestep>();
+        if self.resources().get::<fixed::FixedStage>().is_none() {
+            self.add_resource(fixed::FixedStage);
+            self.add_system_to_stage(stage::FIRST, fixed::accumulate_index_time.system());
+            self.add_stage_after(
+                stage::POST_UPDATE,
+                FIXED_INDEX_UPDATE,
+                SystemStage::parallel().with_run_criteria(fixed::index_tick.system()),
+            );
+        }
+        IndexBuilder::<T>::new()
+            .stage(FIXED_INDEX_UPDATE)
+            .build(self);
+
+        self
+    }
+
+    fn init_index_with_filter<T: IndexKey, F: IndexFilter>(&mut self) -> &mut Self {
+        IndexBuilder::<T>::new().query_filter::<F>().build(self);
+
+        self
+    }
+
+    fn init_index_change_events<T: IndexKey>(&mut self, mode: ChangeEventMode) -> &mut Self {
+        // Sent from LAST, once the index has seen every change of the frame
+        match mode {
+            ChangeEventMode::Granular => self
+                .add_event::<IndexChanged<T>>()
+                .add_system_to_stage(stage::LAST, changes::send_granular_changes::<T>.system()),
+            // Index watchers enable batches too, and each batch must only be sent once
+            ChangeEventMode::Batched => {
+                if self
+                    .resources()
+                    .get::<Events<IndexBatchChanged<T>>>()
+                    .is_none()
+                {
+                    self.add_event::<IndexBatchChanged<T>>()
+                        .add_system_to_stage(
+                            stage::LAST,
+                            changes::send_batched_changes::<T>.system(),
+                        );
+                }
+                self
+            }
+        }
+    }
+
+    fn init_key_occupancy_events<T: IndexKey>(&mut self) -> &mut Self {
+        self.add_event::<KeyOccupancyChanged<T>>()
+            .add_system_to_stage(stage::LAST, changes::send_occupancy_changes::<T>.system())
+    }
+
+    fn add_index_watcher<T: IndexKey, S: System<In = (), Out = ()>>(
+        &mut self,
+        watcher: S,
+    ) -> &mut Self {
+        // Each key type gets its own stage, since run criteria apply to whole stages
+        let stage_name = type_name::<WatchedKeys<T>>();
+        if self.resources().get::<WatchedKeys<T>>().is_none() {
+            // The affected keys are read off the batched change events, which are sent earlier in LAST
+            self.init_index_change_events::<T>(ChangeEventMode::Batched);
+            self.init_resource::<WatchedKeys<T>>();
+            self.add_system_to_stage(stage::LAST, changes::collect_watched_keys::<T>.system());
+            self.add_stage_after(
+                stage::LAST,
+                stage_name,
+                SystemStage::parallel().with_run_criteria(changes::keys_watched::<T>.system()),
+            );
+        }
+        self.add_system_to_stage(stage_name, watcher)
+    }
+
+    fn add_index_sink<T: IndexKey>(&mut self, sink: IndexSink<T>) -> &mut Self {
+        if self.resources().get::<changes::IndexSinks<T>>().is_none() {
+            self.resources()
+                .get_mut::<ComponentIndex<T>>()
+                .expect("The index must be initialized before adding sinks to it")
+                .set_recording(true);
+            self.add_resource(changes::IndexSinks::<T> { sinks: Vec::new() });
+            // Added after the index's own systems, so it also sees anything an optional catch-up pass in LAST did
+            self.add_system_to_stage(stage::LAST, changes::feed_index_sinks::<T>.system());
+        }
+        self.resources()
+            .get_mut::<changes::IndexSinks<T>>()
+            .unwrap()
+            .sinks
+            .push(sink);
+
+        self
+    }
+
+    fn init_multi_key_index<C: Component, K: Eq + Hash + Clone + Send + Sync + 'static>(
+        &mut self,
+        projection: fn(&C) -> Vec<K>,
+    ) -> &mut Self {
+        self.init_resource::<MultiKeyIndex<K>>();
+        self.add_resource(KeyProjection::<C, K> {
+            project: projection,
+        });
+        self.add_startup_system_to_stage(
+            "post_startup",
+            multi_key::update_multi_key_index::<C, K>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            multi_key::update_multi_key_index::<C, K>.system(),
+        );
+
+        self
+    }
+
+    fn init_index_with_ttl<T: IndexKey>(&mut self, clock: TtlClock, default_ttl: f64) -> &mut Self {
+        self.init_index::<T>();
+        self.resources()
+            .get_mut::<ComponentIndex<T>>()
+            .unwrap()
+            .default_ttl = Some(default_ttl);
+
+        match clock {
+            TtlClock::Frames => {
+                self.add_system_to_stage(stage::POST_UPDATE, tick_ttl_frames::<T>.system())
+            }
+            TtlClock::Seconds => {
+                self.add_system_to_stage(stage::POST_UPDATE, tick_ttl_seconds::<T>.system())
+            }
+        }
+    }
+
+    fn init_index_from_events<E: IndexEvent<T>, T: IndexKey>(&mut self) -> &mut Self {
+        self.init_resource::<ComponentIndex<T>>();
+        self.init_resource::<IndexMetrics<T>>();
+        self.add_system_to_stage(stage::FIRST, metrics::reset_index_metrics::<T>.system());
+        self.add_system_to_stage(stage::FIRST, begin_index_frame::<T>.system());
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            events::update_index_from_events::<E, T>.system(),
+        );
+
+        self
+    }
+
+    fn init_fuzzy_index<C: Component>(
+        &mut self,
+        cell_size: f32,
+        projection: fn(&C) -> f32,
+    ) -> &mut Self {
+        self.add_resource(FuzzyIndex::<C>::new(cell_size, projection));
+        self.add_startup_system_to_stage("post_startup", fuzzy::update_fuzzy_index::<C>.system());
+        self.add_system_to_stage(stage::POST_UPDATE, fuzzy::update_fuzzy_index::<C>.system());
+
+        self
+    }
+
+    fn init_time_bucket_index<C: Component>(
+        &mut self,
+        bucket_size: f64,
+        project: fn(&C) -> f64,
+    ) -> &mut Self {
+        self.add_resource(TimeBucketIndex::<C>::new(bucket_size, project));
+        self.add_system_to_stage(
+            stage::FIRST,
+            time_bucket::advance_time_bucket_clock::<C>.system(),
+        );
+        self.add_startup_system_to_stage(
+            "post_startup",
+            time_bucket::update_time_bucket_index::<C>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            time_bucket::update_time_bucket_index::<C>.system(),
+        );
+
+        self
+    }
+
+    fn init_index_category<C: Component, K: IndexKey>(
+        &mut self,
+        categorize: fn(&C) -> K,
+    ) -> &mut Self {
+        // The index is shared by every component categorized into K
+        if self.resources().get::<ComponentIndex<K>>().is_none() {
+            self.init_resource::<ComponentIndex<K>>();
+            self.add_system_to_stage(stage::FIRST, begin_index_frame::<K>.system());
+        }
+        self.add_resource(CategoryProjection::<C, K> { categorize });
+        self.add_startup_system_to_stage(
+            "post_startup",
+            category::update_category_index::<C, K>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            category::update_category_index::<C, K>.system(),
+        );
+
+        self
+    }
+
+    fn init_borrowed_projection<C: Component, K: IndexKey>(
+        &mut self,
+        project: for<'a> fn(&'a C) -> Cow<'a, K>,
+    ) -> &mut Self {
+        self.init_resource::<ComponentIndex<K>>();
+        self.add_resource(BorrowedProjection::<C, K> { project });
+        self.add_system_to_stage(stage::FIRST, begin_index_frame::<K>.system());
+        self.add_startup_system_to_stage(
+            "post_startup",
+            borrowed::update_borrowed_projection_index::<C, K>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            borrowed::update_borrowed_projection_index::<C, K>.system(),
+        );
+
+        self
+    }
+
+    fn init_parent_projection<C: Component, K: IndexKey>(
+        &mut self,
+        project: fn(&C) -> K,
+    ) -> &mut Self {
+        self.init_resource::<ComponentIndex<K>>();
+        self.add_resource(ParentProjection::<C, K> { project });
+        self.add_system_to_stage(stage::FIRST, begin_index_frame::<K>.system());
+        self.add_startup_system_to_stage(
+            "post_startup",
+            hierarchy::update_parent_projection_index::<C, K>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            hierarchy::update_parent_projection_index::<C, K>.system(),
+        );
+
+        self
+    }
+
+    fn init_display_index<C: Component + Display>(&mut self) -> &mut Self {
+        self.init_resource::<DisplayIndex<C>>();
+        self.add_startup_system_to_stage(
+            "post_startup",
+            display::update_display_index::<C>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            display::update_display_index::<C>.system(),
+        );
+
+        self
+    }
+
+    fn init_equiv_index<T: IndexEquiv>(&mut self) -> &mut Self {
+        self.init_index::<T>();
+        self.resources()
+            .get_mut::<ComponentIndex<T>>()
+            .unwrap()
+            .canonicalize = Some(T::canonical);
+
+        self
+    }
+
+    fn init_case_insensitive_index(&mut self) -> &mut Self {
+        self.init_equiv_index::<CaseInsensitiveString>()
+    }
+
+    fn init_normalized_index<T: IndexKey>(&mut self, normalize: fn(&T) -> T) -> &mut Self {
+        self.init_index::<T>();
+        self.resources()
+            .get_mut::<ComponentIndex<T>>()
+            .unwrap()
+            .set_normalization(normalize);
+
+        self
+    }
+
+    fn init_grid_index<C: Component>(
+        &mut self,
+        topology: GridTopology,
+        projection: fn(&C) -> GridCell,
+    ) -> &mut Self {
+        self.add_resource(GridIndex::<C>::new(topology, projection));
+        self.add_startup_system_to_stage("post_startup", grid::update_grid_index::<C>.system());
+        self.add_system_to_stage(stage::POST_UPDATE, grid::update_grid_index::<C>.system());
+
+        self
+    }
+
+    fn init_multi_projection<C: Component>(&mut self) -> MultiProjectionBuilder<'_, C> {
+        self.init_resource::<ProjectionIndexes<C>>();
+        self.add_startup_system_to_stage(
+            "post_startup",
+            projection::update_projection_indexes::<C>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            projection::update_projection_indexes::<C>.system(),
+        );
+
+        MultiProjectionBuilder {
+            app_builder: self,
+            phantom: PhantomData,
+        }
+    }
+
+    fn init_index_tuple<A: IndexKey, B: IndexKey>(&mut self) -> &mut Self {
+        self.init_resource::<ComponentIndex<(A, B)>>();
+        self.add_system_to_stage(stage::FIRST, begin_index_frame::<(A, B)>.system());
+        self.add_startup_system_to_stage(
+            "post_startup",
+            tuple::update_tuple_index::<A, B>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            tuple::update_tuple_index::<A, B>.system(),
+        );
+
+        self
+    }
+
+    fn init_name_index(&mut self) -> &mut Self {
+        self.init_index::<Name>()
+    }
+
+    fn init_lru_index<T: IndexKey>(&mut self, capacity: usize) -> &mut Self {
+        self.add_resource(LruIndex::<T>::new(capacity));
+        self.add_startup_system_to_stage("post_startup", lru::update_lru_index::<T>.system());
+        self.add_system_to_stage(stage::POST_UPDATE, lru::update_lru_index::<T>.system());
+
+        self
+    }
+
+    fn init_handle_index<A: Asset>(&mut self) -> &mut Self {
+        self.init_index::<Handle<A>>()
+    }
+
+    fn init_state_index<S: IndexKey>(&mut self) -> &mut Self {
+        self.init_index::<S>()
+    }
+
+    fn init_adjacency_index(&mut self) -> &mut Self {
+        self.init_resource::<AdjacencyIndex>();
+        self.add_startup_system_to_stage(
+            "post_startup",
+            adjacency::update_adjacency_index.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            adjacency::update_adjacency_index.system(),
+        );
+
+        self
+    }
+
+    fn init_membership_index<C: EntityList>(&mut self) -> &mut Self {
+        self.init_resource::<MembershipIndex<C>>();
+        self.add_startup_system_to_stage(
+            "post_startup",
+            membership::update_membership_index::<C>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            membership::update_membership_index::<C>.system(),
+        );
+
+        self
+    }
+
+    fn init_tag_index<T: Component>(&mut self) -> &mut Self {
+        self.init_resource::<TagIndex<T>>();
+        self.add_startup_system_to_stage("post_startup", tag::update_tag_index::<T>.system());
+        self.add_system_to_stage(stage::POST_UPDATE, tag::update_tag_index::<T>.system());
+
+        self
+    }
+
+    fn init_sharded_index<T: IndexKey>(&mut self, n_shards: usize) -> &mut Self {
+        self.add_resource(ShardedIndex::<T>::new(n_shards));
+        self.add_startup_system_to_stage(
+            "post_startup",
+            sharded::update_sharded_index::<T>.system(),
+        );
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            sharded::update_sharded_index::<T>.system(),
+        );
+
+        self
+    }
+
+    fn init_sparse_index<T: IndexKey>(&mut self) -> &mut Self {
+        self.init_resource::<SparseIndex<T>>();
+        self.add_startup_system_to_stage("post_startup", sparse::update_sparse_index::<T>.system());
+        self.add_system_to_stage(
+            stage::POST_UPDATE,
+            sparse::update_sparse_index::<T>.system(),
+        );
+
+        self
+    }
+
+    fn init_range_index<T: Component + Ord + Clone>(&mut self) -> &mut Self {
+        self.init_resource::<RangeIndex<T>>();
+        self.add_startup_system_to_stage("post_startup", range::update_range_index::<T>.system());
+        self.add_system_to_stage(stage::POST_UPDATE, range::update_range_index::<T>.system());
+
+        self
+    }
+
+    fn init_ord_index<T: Component + Ord + Clone>(&mut self) -> &mut Self {
+        self.init_range_index::<T>()
+    }
+
+    fn update_component_index<T: IndexKey>(
+        mut index: ResMut<ComponentIndex<T>>,
+        mut metrics: ResMut<IndexMetrics<T>>,
+        query: Query<(&T, Entity)>,
+        changed_query: Query<(&T, Entity), Changed<T>>,
+    ) {
+        update_index(
+            &mut index,
+            &mut metrics,
+            || query.iter(),
+            iter_removed::<T, _, _>(&query),
+            changed_query.iter(),
+        );
+    }
+}
+
+// The pass shared by every update system, which only differ in which entities they read.
+// `rows` lists every entity that should be indexed, and is only read when catching up after a pause,
+// `dropped` the entities that may have stopped belonging in the index,
+// and `changed` the entities whose key may have changed, or that may have started belonging in the index.
+pub(crate) fn update_index<'a, T, R, I, D, C>(
+    index: &mut ComponentIndex<T>,
+    metrics: &mut IndexMetrics<T>,
+    rows: R,
+    dropped: D,
+    changed: C,
+) where
+    T: IndexKey,
+    R: FnOnce() -> I,
+    I: IntoIterator<Item = (&'a T, Entity)>,
+    D: IntoIterator<Item = Entity>,
+    C: IntoIterator<Item = (&'a T, Entity)>,
+{
+    if index.paused {
+        return;
+    }
+    let start = Instant::now();
+    let mut stats = UpdateStats::default();
+
+    // Removals and changes that happened while paused are no longer tracked, so compare against the whole world
+    if index.resync_pending {
+        index.resync_pending = false;
+        let current: Vec<(&T, Entity)> = rows().into_iter().collect();
+        let present: HashSet<Entity> = current.iter().map(|(_, entity)| *entity).collect();
+        let stale: Vec<Entity> = index
+            .reverse
+            .keys()
+            .copied()
+            .filter(|entity| !present.contains(entity))
+            .collect();
+        for entity in stale {
+            index.remove(&entity);
+            metrics.record_remove();
+            stats.removed += 1;
+        }
+        for (component, entity) in current {
+            if !index.is_stored_as(entity, component) {
+                apply_update(index, metrics, &mut stats, entity, component);
+            }
+        }
+    }
+
+    // First, clean up any entities who had this component removed
+    for entity in dropped {
+        if index.value_of(entity).is_some() {
+            index.remove(&entity);
+            metrics.record_remove();
+            stats.removed += 1;
+        }
+    }
+
+    // Add in new values for the changed records to the forward and reverse entries
+    for (component, entity) in changed {
+        // Change flags persist for the whole frame, so later passes will see the same changes again
+        if index.is_stored_as(entity, component) {
+            stats.skipped += 1;
+        } else {
+            apply_update(index, metrics, &mut stats, entity, component);
+        }
+    }
+
+    if !index.updated || stats.changed_index() {
+        index.last_update = stats;
+    }
+    index.updated = true;
+    index.debug_assert_consistent();
+    metrics.time_spent += start.elapsed();
+}
+
+// Stores `entity` under its changed `key` on behalf of the update system, counting what happened in `stats`
+fn apply_update<T: IndexKey>(
+    index: &mut ComponentIndex<T>,
+    metrics: &mut IndexMetrics<T>,
+    stats: &mut UpdateStats,
+    entity: Entity,
+    key: &T,
+) {
+    let was_indexed = index.value_of(entity).is_some();
+    if index.validate(entity, key) {
+        index.insert(key.clone(), entity);
+        metrics.record_insert();
+        if was_indexed {
+            stats.reassigned += 1;
+        } else {
+            stats.inserted += 1;
+        }
+    } else if was_indexed {
+        metrics.record_remove();
+        stats.removed += 1;
+    } else {
+        stats.skipped += 1;
+    }
+}
+
+/// Follows the first entity stored under `key` in `a` over to `b`, returning its key there.
+/// Useful for two-step lookups, such as finding the team of whichever unit stands on a tile.
+pub fn hop<'b, A: Hash + Eq, B: Hash + Eq>(
+    a: &ComponentIndex<A>,
+    b: &'b ComponentIndex<B>,
+    key: &A,
+) -> Option<&'b B> {
+    a.entities_of(key)
+        .and_then(|entities| entities.first())
+        .and_then(|entity| b.value_of(*entity))
+}
+
+// Entities in exactly one of `a` and `b`, in the order they appear
+pub(crate) fn symmetric_difference(a: &[Entity], b: &[Entity]) -> Vec<Entity> {
+    a.iter()
+        .filter(|e| !b.contains(e))
+        .chain(b.iter().filter(|e| !a.contains(e)))
+        .copied()
+        .collect()
+}
+
+fn begin_index_frame<T: IndexKey>(mut index: ResMut<ComponentIndex<T>>) {
+    index.begin_frame();
+}
+
+/// A run criteria that only runs a stage on frames where the `ComponentIndex<T>` changed,
+/// so systems that read the index can skip idle frames.
+/// The index is updated in `POST_UPDATE`, so the gated stage should be placed after it.
+pub fn index_changed<T: IndexKey>(index: Res<ComponentIndex<T>>) -> ShouldRun {
+    if index.changed_this_frame() {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+fn tick_ttl_frames<T: IndexKey>(mut index: ResMut<ComponentIndex<T>>) {
+    index.advance_clock(1.0);
+}
+
+fn tick_ttl_seconds<T: IndexKey>(time: Res<Time>, mut index: ResMut<ComponentIndex<T>>) {
+    index.advance_clock(time.delta_seconds_f64());
+}
+
+/// Runs `$body` for each entity stored under `$key` in `$index`,
+/// binding `$entity` and destructuring the components fetched from `$query` into `$components`.
+/// Pass the query as `&mut query` to fetch the components mutably.
+/// Entities that don't match the query are skipped.
+#[macro_export]
+macro_rules! index_join {
+    ($index:expr, $key:expr, &mut $query:ident, |$entity:ident, $components:pat| $body:block) => {
+        for &$entity in $index.get($key).iter() {
+            if let Ok($components) = $query.get_mut($entity) $body
+        }
+    };
+    ($index:expr, $key:expr, &$query:ident, |$entity:ident, $components:pat| $body:block) => {
+        for &$entity in $index.get($key).iter() {
+            if let Ok($components) = $query.get($entity) $body
+        }
+    };
+}
+
+pub trait IndexCommands {
+    /// Refreshes the index entry of a single entity at the next command flush
+    fn reindex<T: IndexKey>(&mut self, entity: Entity) -> &mut Self;
+
+    /// Spawns a batch of entities and records their `T` in the index as soon as the commands are flushed,
+    /// rather than waiting for the next `POST_UPDATE`
+    fn spawn_indexed_batch<T: IndexKey, I>(&mut self, bundles_iter: I) -> &mut Self
+    where
+        I: IntoIterator + Send + Sync + 'static,
+        I::Item: Bundle;
+}
+
+impl IndexCommands for Commands {
+    fn reindex<T: IndexKey>(&mut self, entity: Entity) -> &mut Self {
+        self.add_command(Reindex::<T> {
+            entity,
+            phantom: PhantomData,
+        })
+    }
+
+    fn spawn_indexed_batch<T: IndexKey, I>(&mut self, bundles_iter: I) -> &mut Self
+    where
+        I: IntoIterator + Send + Sync + 'static,
+        I::Item: Bundle,
+    {
+        self.add_command(SpawnIndexedBatch::<T, I> {
+            bundles_iter,
+            phantom: PhantomData,
+        })
+    }
+}
+
+struct Reindex<T: IndexKey> {
+    entity: Entity,
+    phantom: PhantomData<T>,
+}
+
+impl<T: IndexKey> Command for Reindex<T> {
+    fn write(self: Box<Self>, world: &mut World, resources: &mut Resources) {
+        let mut index = resources
+            .get_mut::<ComponentIndex<T>>()
+            .expect("reindex called for a component type without an index");
+
+        // Goes through the same checks as the update systems, so reindexing can't sneak in an entity they'd reject
+        let matches_filter = index
+            .filter
+            .map_or(true, |filter| filter(world, self.entity));
+        match world.get::<T>(self.entity) {
+            Ok(component) if matches_filter => {
+                if !index.is_stored_as(self.entity, component)
+                    && index.validate(self.entity, component)
+                {
+                    index.insert(component.clone(), self.entity);
+                }
+            }
+            // The entity or its component is gone, or it no longer matches the filter, so it shouldn't be indexed at all
+            _ => index.remove(&self.entity),
+        }
+    }
+}
+
+struct SpawnIndexedBatch<T: IndexKey, I> {
+    bundles_iter: I,
+    phantom: PhantomData<T>,
+}
+
+impl<T: IndexKey, I> Command for SpawnIndexedBatch<T, I>
+where
+    I: IntoIterator + Send + Sync + 'static,
+    I::Item: Bundle,
+{
+    fn write(self: Box<Self>, world: &mut World, resources: &mut Resources) {
+        let entities: Vec<Entity> = world.spawn_batch(self.bundles_iter).collect();
+
+        let mut index = resources
+            .get_mut::<ComponentIndex<T>>()
+            .expect("spawn_indexed_batch called for a component type without an index");
+        for entity in entities {
+            if let Ok(component) = world.get::<T>(entity) {
+                index.insert(component.clone(), entity);
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+
+    use super::*;
+    #[cfg(test)]
+    use crate::test_utils::assert_indexed;
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct MyStruct {
+        val: i8,
+    }
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct MyTupleStruct(i8);
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct MyCompoundStruct {
+        val: i8,
+        name: String,
+    }
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    enum MyEnum {
+        Red,
+        Blue,
+    }
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct Position {
+        x: i8,
+        y: i8,
+    }
+
+    const GOOD_NUMBER: i8 = 42;
+    const BAD_NUMBER: i8 = 0;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Goodness {
+        Good,
+        Bad,
+        Confused,
+    }
+
+    fn spawn_bad_entity(commands: &mut Commands) {
+        commands.spawn((MyStruct { val: BAD_NUMBER }, Goodness::Bad));
+    }
+
+    fn spawn_good_entity(commands: &mut Commands) {
+        commands.spawn((MyStruct { val: GOOD_NUMBER }, Goodness::Good));
+    }
+
+    fn spawn_deficient_entity(commands: &mut Commands) {
+        commands.spawn((Goodness::Good,));
+    }
+
+    fn augment_entities(
+        commands: &mut Commands,
+        query: Query<Entity, (With<Goodness>, Without<MyStruct>)>,
+    ) {
+        for e in query.iter() {
+            commands.insert(e, (MyStruct { val: GOOD_NUMBER },));
+        }
+    }
+
+    fn reform_entities(
+        mut query: Query<(&mut Goodness, &mut MyStruct)>,
+        index: Res<ComponentIndex<MyStruct>>,
+    ) {
+        let entities = index.get(&MyStruct { val: BAD_NUMBER });
+
+        for e in entities.iter() {
+            let (mut goodness, mut val) = query.get_mut(*e).unwrap();
+            *goodness = Goodness::Good;
+            *val = MyStruct { val: GOOD_NUMBER };
+        }
+    }
+
+    fn purge_badness(commands: &mut Commands, index: Res<ComponentIndex<MyStruct>>) {
+        let entities = index.get(&MyStruct { val: BAD_NUMBER });
+
+        for e in entities.iter() {
+            commands.despawn(*e);
+        }
+    }
+
+    fn ensure_goodness(query: Query<&Goodness>, index: Res<ComponentIndex<MyStruct>>) {
+        let entities = index.get(&MyStruct { val: GOOD_NUMBER });
+
+        // Each test must have at least one matching example when checked
+        assert!(entities.len() >= 1);
+
+        // Each entity with MyStruct.val = GOOD_NUMBER is Good
+        for e in entities.iter() {
+            assert_eq!(
+                query
+                    .get_component::<Goodness>(*e)
+                    .unwrap_or(&Goodness::Confused),
+                &Goodness::Good
+            );
+        }
+    }
+
+    fn ensure_absence_of_bad(query: Query<&Goodness>, index: Res<ComponentIndex<MyStruct>>) {
+        let entities = index.get(&MyStruct { val: BAD_NUMBER });
+
+        assert!(entities.len() == 0);
+
+        for goodness in query.iter() {
+            assert!(*goodness != Goodness::Bad);
+        }
+    }
+
+    fn debug_index(index: Res<ComponentIndex<MyStruct>>) {
+        dbg!(index);
+    }
+
+    #[test]
+    fn struct_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        app_builder.run();
+    }
+
+    #[test]
+    fn tuple_struct_test() {
+        App::build().init_index::<MyTupleStruct>().run()
+    }
+    #[test]
+    fn compound_struct_test() {
+        App::build().init_index::<MyCompoundStruct>().run()
+    }
+    #[test]
+    fn enum_test() {
+        App::build().init_index::<MyEnum>().run()
+    }
+
+    #[test]
+    fn startup_spawn_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_good_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system_to_stage(stage::FIRST, ensure_goodness.system())
+            .run()
+    }
+
+    #[test]
+    fn update_spawn_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_system(spawn_good_entity.system())
+            .add_system(spawn_bad_entity.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness.system())
+            .run()
+    }
+
+    #[test]
+    fn duplicate_spawn_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_system(spawn_good_entity.system())
+            .add_system(spawn_good_entity.system())
+            .add_system(spawn_bad_entity.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness.system())
+            .run()
+    }
+
+    #[test]
+    fn component_added_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_deficient_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system(augment_entities.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness.system())
+            .run()
+    }
+    #[test]
+    fn component_modified_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_bad_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system(reform_entities.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness.system())
+            .run()
+    }
+
+    #[test]
+    fn entity_removal_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system(purge_badness.system())
+            .add_system_to_stage(stage::LAST, ensure_absence_of_bad.system())
+            .run()
+    }
+
+    #[test]
+    fn duplicate_removal_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_bad_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system(purge_badness.system())
+            .add_system_to_stage(stage::LAST, ensure_absence_of_bad.system())
+            .run()
+    }
+
+    #[test]
+    #[should_panic] // Commands don't get processed until the end of the current Stage
+    fn same_stage_addition_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_system(spawn_deficient_entity.system())
+            .add_system(augment_entities.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness.system())
+            .run()
+    }
+
+    #[test]
+    #[should_panic] // Commands don't get processed until the end of the current Stage
+    fn same_stage_modification_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_system(spawn_bad_entity.system())
+            .add_system(reform_entities.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness.system())
+            .run()
+    }
+
+    #[test]
+    #[should_panic] // Commands don't get processed until the end of the current Stage
+    fn same_stage_removal_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_system(spawn_bad_entity.system())
+            .add_system(purge_badness.system())
+            .add_system_to_stage(stage::LAST, ensure_absence_of_bad.system())
+            .run()
+    }
+
+    #[test]
+    fn earlier_stage_addition_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_system_to_stage(stage::PRE_UPDATE, spawn_deficient_entity.system())
+            .add_system(augment_entities.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness.system())
+            .run()
+    }
+
+    #[test]
+    fn accessors_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_good_entity.system())
+            .add_startup_system(spawn_good_entity.system())
+            .add_startup_system(spawn_bad_entity.system());
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+
+        for (key, entities) in index.forward().iter_all() {
+            assert_eq!(index.get(key).as_ref(), entities.as_slice());
+        }
+
+        assert_eq!(index.reverse().len(), 3);
+        for (entity, key) in index.reverse().iter() {
+            assert_eq!(index.value_of(*entity), Some(key));
+        }
+    }
+
+    fn reform_and_reindex_first(
+        commands: &mut Commands,
+        mut query: Query<(&mut MyStruct, Entity)>,
+    ) {
+        let mut reindexed = false;
+        for (mut val, entity) in query.iter_mut() {
+            *val = MyStruct { val: GOOD_NUMBER };
+            if !reindexed {
+                commands.reindex::<MyStruct>(entity);
+                reindexed = true;
+            }
+        }
+    }
+
+    fn ensure_single_reindex(index: Res<ComponentIndex<MyStruct>>) {
+        // Only the reindexed entity has been moved before POST_UPDATE runs
+        assert_eq!(index.get(&MyStruct { val: GOOD_NUMBER }).len(), 1);
+        assert_eq!(index.get(&MyStruct { val: BAD_NUMBER }).len(), 1);
+    }
+
+    #[test]
+    fn reindex_command_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_bad_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system(reform_and_reindex_first.system())
+            .add_stage_after(stage::UPDATE, "check_reindex", SystemStage::parallel())
+            .add_system_to_stage("check_reindex", ensure_single_reindex.system())
+            .run()
+    }
+
+    struct Hidden;
+
+    #[test]
+    fn reindex_checks_test() {
+        let mut app_builder = App::build();
+        IndexBuilder::<MyStruct>::new()
+            .query_filter::<Without<Hidden>>()
+            .build(&mut app_builder);
+        app_builder
+            .resources()
+            .get_mut::<ComponentIndex<MyStruct>>()
+            .unwrap()
+            .set_validation(|key| key.val != BAD_NUMBER);
+        let world = &mut app_builder.app.world;
+        let visible = world.spawn((MyStruct { val: GOOD_NUMBER },));
+        let hidden = world.spawn((MyStruct { val: GOOD_NUMBER }, Hidden));
+        let invalid = world.spawn((MyStruct { val: BAD_NUMBER },));
+
+        let app = &mut app_builder.app;
+        for entity in [visible, hidden, invalid].iter() {
+            let reindex = Box::new(Reindex::<MyStruct> {
+                entity: *entity,
+                phantom: PhantomData,
+            });
+            reindex.write(&mut app.world, &mut app.resources);
+        }
+
+        assert_indexed(&app_builder.app, &MyStruct { val: GOOD_NUMBER }, &[visible]);
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert_eq!(index.value_of(hidden), None);
+        assert_eq!(index.value_of(invalid), None);
+    }
+
+    fn reform_entities_joined(
+        mut query: Query<(&mut Goodness, &mut MyStruct)>,
+        index: Res<ComponentIndex<MyStruct>>,
+    ) {
+        index_join!(
+            index,
+            &MyStruct { val: BAD_NUMBER },
+            &mut query,
+            |_entity, (mut goodness, mut val)| {
+                *goodness = Goodness::Good;
+                *val = MyStruct { val: GOOD_NUMBER };
+            }
+        );
+    }
+
+    fn ensure_goodness_joined(query: Query<&Goodness>, index: Res<ComponentIndex<MyStruct>>) {
+        let mut n_good = 0;
+        index_join!(
+            index,
+            &MyStruct { val: GOOD_NUMBER },
+            &query,
+            |entity, goodness| {
+                assert_eq!(goodness, &Goodness::Good, "{:?} is not good", entity);
+                n_good += 1;
+            }
+        );
+
+        assert!(n_good >= 1);
+    }
+
+    #[test]
+    fn index_join_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_bad_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system(reform_entities_joined.system())
+            .add_system_to_stage(stage::LAST, ensure_goodness_joined.system())
+            .run()
+    }
+
+    #[test]
+    fn ttl_eviction_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index_with_ttl::<MyStruct>(TtlClock::Frames, 100.0);
+        app_builder.app.update();
+
+        let short_lived = Entity::new(1000);
+        let long_lived = Entity::new(1001);
+        {
+            let mut index = app_builder
+                .resources()
+                .get_mut::<ComponentIndex<MyStruct>>()
+                .unwrap();
+            index.insert_with_ttl(MyStruct { val: BAD_NUMBER }, short_lived, 2.0);
+            index.insert(MyStruct { val: GOOD_NUMBER }, long_lived);
+        }
+
+        app_builder.app.update();
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<MyStruct>>()
+                .unwrap();
+            assert_eq!(
+                index.value_of(short_lived),
+                Some(&MyStruct { val: BAD_NUMBER })
+            );
+        }
+
+        app_builder.app.update();
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert_eq!(index.value_of(short_lived), None);
+        assert!(index.get(&MyStruct { val: BAD_NUMBER }).is_empty());
+        assert_eq!(
+            index.value_of(long_lived),
+            Some(&MyStruct { val: GOOD_NUMBER })
+        );
+    }
+
+    #[test]
+    fn lookup_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let good = MyStruct { val: GOOD_NUMBER };
+        let bad = MyStruct { val: BAD_NUMBER };
+        let entity = Entity::new(0);
+
+        assert_eq!(index.lookup(&good), Lookup::Missing);
+
+        index.insert(good.clone(), entity);
+        assert_eq!(index.lookup(&good), Lookup::Entities(&[entity]));
+
+        index.insert(bad.clone(), entity);
+        assert_eq!(index.lookup(&good), Lookup::Empty);
+        assert_eq!(index.lookup(&bad), Lookup::Entities(&[entity]));
+
+        index.remove(&entity);
+        assert_eq!(index.lookup(&bad), Lookup::Empty);
+    }
+
+    #[test]
+    fn par_for_each_test() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let good = MyStruct { val: GOOD_NUMBER };
+        for id in 0..1000 {
+            index.insert(good.clone(), Entity::new(id));
+        }
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(1000));
+
+        let serial_sum: u64 = index.get(&good).iter().map(|e| e.id() as u64).sum();
+
+        let parallel_sum = AtomicU64::new(0);
+        index.par_for_each(&TaskPool::new(), &good, |entity| {
+            parallel_sum.fetch_add(entity.id() as u64, Ordering::Relaxed);
+        });
+
+        assert_eq!(parallel_sum.into_inner(), serial_sum);
+    }
+
+    #[test]
+    fn map_keys_test() {
+        let mut index = ComponentIndex::<Position>::new();
+        let a = Entity::new(0);
+        let b = Entity::new(1);
+        let c = Entity::new(2);
+        index.insert(Position { x: 0, y: 0 }, a);
+        index.insert(Position { x: 1, y: 0 }, b);
+        index.insert(Position { x: 1, y: 0 }, c);
+
+        index.map_keys(|p| Position { x: p.x + 1, y: p.y });
+        assert!(index.get(&Position { x: 0, y: 0 }).is_empty());
+        assert_eq!(index.get(&Position { x: 1, y: 0 }).as_ref(), &[a]);
+        assert_eq!(index.get(&Position { x: 2, y: 0 }).len(), 2);
+        assert_eq!(index.value_of(b), Some(&Position { x: 2, y: 0 }));
+
+        // Collapsing every column onto x = 0 merges all of the entities
+        index.map_keys(|p| Position { x: 0, y: p.y });
+        let mut merged = index.get(&Position { x: 0, y: 0 }).into_owned();
+        merged.sort();
+        assert_eq!(merged, vec![a, b, c]);
+        for entity in [a, b, c].iter() {
+            assert_eq!(index.value_of(*entity), Some(&Position { x: 0, y: 0 }));
+        }
+    }
+
+    #[test]
+    fn map_keys_order_test() {
+        let (a, b, c) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        let build = |track: bool| {
+            let mut index = ComponentIndex::<Position>::new();
+            index.set_track_insertion_order(track);
+            index.insert(Position { x: 1, y: 0 }, c);
+            index.insert(Position { x: 2, y: 0 }, b);
+            index.insert(Position { x: 1, y: 0 }, a);
+            index.map_keys(|p| Position { x: 0, y: p.y });
+            index
+        };
+
+        // Each merged key keeps its own order, with the lists ordered by their first entity
+        let untracked = build(false);
+        assert_eq!(untracked.get(&Position { x: 0, y: 0 }).as_ref(), &[b, c, a]);
+
+        let tracked = build(true);
+        assert_eq!(tracked.get(&Position { x: 0, y: 0 }).as_ref(), &[c, b, a]);
+    }
+
+    #[test]
+    fn deferred_insertion_test() {
+        let mut app_builder = App::build();
+        IndexBuilder::<MyStruct>::new()
+            .catch_up_in_last()
+            .build(&mut app_builder);
+        app_builder
+            .add_startup_system(spawn_deficient_entity.system())
+            .add_system_to_stage(stage::POST_UPDATE, augment_entities.system());
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert_eq!(index.get(&MyStruct { val: GOOD_NUMBER }).len(), 1);
+    }
+
+    #[test]
+    fn dump_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(0));
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(1));
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(2));
+        index.insert(MyStruct { val: 7 }, Entity::new(3));
+        index.remove(&Entity::new(3));
+
+        assert_eq!(
+            index.dump(),
+            "MyStruct { val: 0 }: 2\nMyStruct { val: 42 }: 1"
+        );
+    }
+
+    #[test]
+    fn first_last_test() {
+        let mut index = ComponentIndex::<Position>::new();
+        let tile = Position { x: 0, y: 0 };
+        let (bottom, middle, top) = (Entity::new(0), Entity::new(1), Entity::new(2));
+
+        assert_eq!(index.first(&tile), None);
+        assert_eq!(index.last(&tile), None);
+
+        index.insert(tile, bottom);
+        index.insert(tile, middle);
+        index.insert(tile, top);
+        assert_eq!(index.first(&tile), Some(bottom));
+        assert_eq!(index.last(&tile), Some(top));
+
+        index.remove(&top);
+        assert_eq!(index.last(&tile), Some(middle));
+        index.remove(&bottom);
+        assert_eq!(index.first(&tile), Some(middle));
+
+        // Re-inserting an entity moves it to the back of its key
+        index.insert(tile, bottom);
+        assert_eq!(index.first(&tile), Some(middle));
+        assert_eq!(index.last(&tile), Some(bottom));
+
+        index.remove(&middle);
+        index.remove(&bottom);
+        assert_eq!(index.first(&tile), None);
+        assert_eq!(index.last(&tile), None);
+    }
+
+    #[test]
+    fn export_import_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(0));
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(1));
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(2));
+
+        let state = index.export_state();
+        let expected_dump = index.dump();
+        drop(index);
+
+        let mut restored = ComponentIndex::<MyStruct>::new();
+        restored.insert(MyStruct { val: 7 }, Entity::new(3));
+        restored.import_state(state.clone());
+
+        for (key, entities) in state.entries.iter() {
+            assert_eq!(restored.get(key).as_ref(), entities.as_slice());
+        }
+        assert_eq!(restored.dump(), expected_dump);
+        assert_eq!(restored.value_of(Entity::new(3)), None);
+        assert_eq!(
+            restored.value_of(Entity::new(1)),
+            Some(&MyStruct { val: GOOD_NUMBER })
+        );
+    }
+
+    #[test]
+    fn metrics_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let moved = world.spawn((MyStruct { val: BAD_NUMBER },));
+        let despawned = world.spawn((MyStruct { val: BAD_NUMBER },));
+        app_builder.app.update();
+
+        let world = &mut app_builder.app.world;
+        world.get_mut::<MyStruct>(moved).unwrap().val = GOOD_NUMBER;
+        world.despawn(despawned).unwrap();
+        world.spawn((MyStruct { val: BAD_NUMBER },));
+        app_builder.app.update();
+
+        let metrics = app_builder
+            .resources()
+            .get::<IndexMetrics<MyStruct>>()
+            .unwrap();
+        assert_eq!(metrics.inserts, 2);
+        assert_eq!(metrics.removes, 1);
+    }
+
+    fn populate_index(
+        mut index: ResMut<ComponentIndex<MyStruct>>,
+        query: Query<(&MyStruct, Entity)>,
+    ) {
+        index.populate_from(&query);
+    }
+
+    fn ensure_complete(index: Res<ComponentIndex<MyStruct>>, query: Query<(&MyStruct, Entity)>) {
+        assert_eq!(index.reverse().len(), 3);
+        for (component, entity) in query.iter() {
+            assert_eq!(index.value_of(entity), Some(component));
+        }
+    }
+
+    #[test]
+    fn populate_from_test() {
+        App::build()
+            .init_resource::<ComponentIndex<MyStruct>>()
+            .add_startup_system(spawn_good_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_startup_system(spawn_bad_entity.system())
+            .add_system(populate_index.system())
+            .add_system_to_stage(stage::LAST, ensure_complete.system())
+            .run()
+    }
+
+    fn spawn_many_values(commands: &mut Commands) {
+        for i in 0..30 {
+            commands.spawn((MyStruct { val: i % 5 },));
+        }
+    }
+
+    fn ensure_key_count(index: Res<ComponentIndex<MyStruct>>) {
+        assert_eq!(index.key_count(), 5);
+    }
+
+    #[test]
+    fn key_count_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_startup_system(spawn_many_values.system())
+            .add_system(ensure_key_count.system())
+            .run();
+
+        // Emptied keys are not counted
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(0));
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(0));
+        assert_eq!(index.key_count(), 1);
+    }
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct TorusPosition {
+        x: i8,
+        y: i8,
+    }
+
+    impl IndexEquiv for TorusPosition {
+        fn canonical(&self) -> Self {
+            TorusPosition {
+                x: self.x.rem_euclid(10),
+                y: self.y.rem_euclid(10),
+            }
+        }
+    }
+
+    #[test]
+    fn equiv_index_test() {
+        let mut app_builder = App::build();
+        app_builder.init_equiv_index::<TorusPosition>();
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((TorusPosition { x: 2, y: 0 },));
+        let b = world.spawn((TorusPosition { x: 12, y: 0 },));
+        let c = world.spawn((TorusPosition { x: -8, y: 10 },));
+        let d = world.spawn((TorusPosition { x: 3, y: 0 },));
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<TorusPosition>>()
+            .unwrap();
+        let mut wrapped = index.get(&TorusPosition { x: 22, y: -10 }).into_owned();
+        wrapped.sort();
+        assert_eq!(wrapped, vec![a, b, c]);
+        assert_eq!(index.get(&TorusPosition { x: -7, y: 0 }).as_ref(), &[d]);
+        assert_eq!(index.value_of(b), Some(&TorusPosition { x: 2, y: 0 }));
+    }
+
+    #[test]
+    fn reassigned_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let mover = world.spawn((MyStruct { val: BAD_NUMBER },));
+        let stayer = world.spawn((MyStruct { val: BAD_NUMBER },));
+        let despawned = world.spawn((MyStruct { val: BAD_NUMBER },));
+        app_builder.app.update();
+
+        let world = &mut app_builder.app.world;
+        world.get_mut::<MyStruct>(mover).unwrap().val = GOOD_NUMBER;
+        // Mutated, but still under the same key
+        world.get_mut::<MyStruct>(stayer).unwrap().val = BAD_NUMBER;
+        world.despawn(despawned).unwrap();
+        world.spawn((MyStruct { val: GOOD_NUMBER },));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<MyStruct>>()
+                .unwrap();
+            assert_eq!(index.reassigned_this_frame(), &[mover]);
+        }
+
+        app_builder.app.update();
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert!(index.reassigned_this_frame().is_empty());
+    }
+
+    #[test]
+    fn replace_with_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.set_track_insertion_order(true);
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(0));
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(1));
+
+        let mut rebuilt = ComponentIndex::<MyStruct>::new();
+        rebuilt.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(1));
+        rebuilt.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(2));
+
+        index.set_track_frame_changes(true);
+        index.replace_with(rebuilt);
+        assert!(index.get(&MyStruct { val: BAD_NUMBER }).is_empty());
+        assert_eq!(
+            index.get(&MyStruct { val: GOOD_NUMBER }).as_ref(),
+            &[Entity::new(1), Entity::new(2)]
+        );
+        assert_eq!(index.value_of(Entity::new(0)), None);
+        assert_eq!(
+            index.value_of(Entity::new(1)),
+            Some(&MyStruct { val: GOOD_NUMBER })
+        );
+        assert_eq!(index.evicted_this_frame(), &[Entity::new(0)]);
+        assert_eq!(index.reassigned_this_frame(), &[Entity::new(1)]);
+        assert_eq!(index.added_this_frame(), &[Entity::new(2)]);
+        assert_eq!(
+            index.iter_in_insertion_order().collect::<Vec<Entity>>(),
+            vec![Entity::new(1), Entity::new(2)]
+        );
+        assert!(index.changed_this_frame());
+    }
+
+    #[test]
+    fn replace_with_normalization_test() {
+        let mut index = ComponentIndex::<i32>::new();
+        index.set_normalization(|key| key.abs());
+        index.insert(-3, Entity::new(0));
+
+        let mut rebuilt = ComponentIndex::<i32>::new();
+        rebuilt.insert(-3, Entity::new(0));
+        rebuilt.insert(-5, Entity::new(1));
+
+        // Keys built by an index without the normalization are normalized on the way in
+        index.replace_with(rebuilt);
+        assert_eq!(index.value_of(Entity::new(1)), Some(&5));
+        assert_eq!(index.get(&-5).as_ref(), &[Entity::new(1)]);
+        assert_eq!(index.get(&3).as_ref(), &[Entity::new(0)]);
+        assert_eq!(index.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn replace_with_order_test() {
+        let key = MyStruct { val: GOOD_NUMBER };
+        let (a, b) = (Entity::new(0), Entity::new(1));
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.insert(key.clone(), a);
+        index.insert(key.clone(), b);
+
+        // Same entities under the same key, only in a different order
+        let mut rebuilt = ComponentIndex::<MyStruct>::new();
+        rebuilt.insert(key.clone(), b);
+        rebuilt.insert(key.clone(), a);
+
+        index.set_track_frame_changes(true);
+        let expected = rebuilt.snapshot();
+        index.replace_with(rebuilt);
+        assert_eq!(index.get(&key).as_ref(), &[b, a]);
+        assert_eq!(index, expected);
+        assert!(!index.changed_this_frame());
+        assert_eq!(index.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn entry_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let (a, b, c) = (Entity::new(0), Entity::new(1), Entity::new(2));
+
+        index.entry(MyStruct { val: BAD_NUMBER }).push(a).push(b);
+        let mut good = index.entry(MyStruct { val: GOOD_NUMBER });
+        assert!(good.entities().is_empty());
+        good.push(c).push(b);
+        assert_eq!(good.entities(), &[c, b]);
+
+        assert_eq!(index.get(&MyStruct { val: BAD_NUMBER }).as_ref(), &[a]);
+        assert_eq!(index.get(&MyStruct { val: GOOD_NUMBER }).as_ref(), &[c, b]);
+        assert_eq!(index.value_of(b), Some(&MyStruct { val: GOOD_NUMBER }));
+        // Creating an entry without pushing leaves an empty key behind
+        index.entry(MyStruct { val: 7 });
+        assert_eq!(index.lookup(&MyStruct { val: 7 }), Lookup::Empty);
+    }
+
+    #[test]
+    fn name_index_test() {
+        let mut app_builder = App::build();
+        app_builder.init_name_index();
+        let world = &mut app_builder.app.world;
+        let player = world.spawn((Name::new("Player"),));
+        let goblin_1 = world.spawn((Name::new("Goblin"),));
+        let goblin_2 = world.spawn((Name::new("Goblin"),));
+        app_builder.app.update();
+
+        let app = &app_builder.app;
+        assert_indexed(app, &Name::new("Player"), &[player]);
+        assert_indexed(app, &Name::new("Goblin"), &[goblin_1, goblin_2]);
+
+        *app_builder.app.world.get_mut::<Name>(goblin_1).unwrap() = Name::new("Goblin King");
+        app_builder.app.update();
+
+        let app = &app_builder.app;
+        assert_indexed(app, &Name::new("Goblin"), &[goblin_2]);
+        assert_indexed(app, &Name::new("Goblin King"), &[goblin_1]);
+    }
+
+    #[test]
+    fn value_of_or_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let fallback = MyStruct { val: BAD_NUMBER };
+        let indexed = Entity::new(0);
+        index.insert(MyStruct { val: GOOD_NUMBER }, indexed);
+
+        assert_eq!(
+            index.value_of_or(indexed, &fallback),
+            &MyStruct { val: GOOD_NUMBER }
+        );
+        assert_eq!(index.value_of_or(Entity::new(1), &fallback), &fallback);
+    }
+
+    #[test]
+    fn entities_len_churn_test() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut index = ComponentIndex::<MyStruct>::new();
+
+        for _ in 0..1000 {
+            let entity = Entity::new(rng.gen_range(0, 50));
+            if rng.gen_bool(0.25) {
+                index.remove(&entity);
+            } else {
+                index.insert(
+                    MyStruct {
+                        val: rng.gen_range(0, 8),
+                    },
+                    entity,
+                );
+            }
+
+            for val in 0..8 {
+                let key = MyStruct { val };
+                let n_stored = index.reverse().values().filter(|k| **k == key).count();
+                assert_eq!(index.entities_len(&key), n_stored);
+                assert_eq!(index.entities_len(&key), index.get(&key).len());
+            }
+        }
+    }
+
+    fn spawn_good_batch(commands: &mut Commands) {
+        commands.spawn_indexed_batch::<MyStruct, _>(
+            (0..10).map(|_| (MyStruct { val: GOOD_NUMBER }, Goodness::Good)),
+        );
+    }
+
+    fn ensure_batch_indexed(index: Res<ComponentIndex<MyStruct>>) {
+        // Checked before POST_UPDATE has had a chance to index the new entities
+        assert_eq!(index.get(&MyStruct { val: GOOD_NUMBER }).len(), 10);
+    }
+
+    #[test]
+    fn spawn_indexed_batch_test() {
+        App::build()
+            .init_index::<MyStruct>()
+            .add_system(spawn_good_batch.system())
+            .add_stage_after(stage::UPDATE, "check_batch", SystemStage::parallel())
+            .add_system_to_stage("check_batch", ensure_batch_indexed.system())
+            .run()
+    }
+
+    fn corrupted_index(policy: ConsistencyPolicy) -> ComponentIndex<MyStruct> {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.set_consistency_policy(policy);
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(0));
+        // Skip the forward map entirely
+        index
+            .reverse
+            .insert(Entity::new(1), MyStruct { val: BAD_NUMBER });
+
+        index
+    }
+
+    #[test]
+    fn consistency_check_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(0));
+        index.insert(MyStruct { val: BAD_NUMBER }, Entity::new(0));
+        assert_eq!(index.find_inconsistency(), None);
+
+        assert!(corrupted_index(ConsistencyPolicy::Ignore)
+            .find_inconsistency()
+            .is_some());
+    }
+
+    #[test]
+    fn ignore_policy_test() {
+        corrupted_index(ConsistencyPolicy::Ignore).debug_assert_consistent();
+    }
+
+    #[test]
+    fn warn_policy_test() {
+        corrupted_index(ConsistencyPolicy::Warn).debug_assert_consistent();
+    }
+
+    #[test]
+    #[should_panic]
+    fn panic_policy_test() {
+        corrupted_index(ConsistencyPolicy::Panic).debug_assert_consistent();
+    }
+
+    #[test]
+    fn is_indexed_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let indexed = world.spawn((MyStruct { val: GOOD_NUMBER },));
+        let evicted = world.spawn((MyStruct { val: BAD_NUMBER },));
+        let never = world.spawn((Score(0),));
+        app_builder.app.update();
+
+        app_builder
+            .app
+            .world
+            .remove_one::<MyStruct>(evicted)
+            .unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert!(index.is_indexed(indexed));
+        assert!(!index.is_indexed(evicted));
+        assert!(!index.is_indexed(never));
+    }
+
+    #[test]
+    fn siblings_test() {
+        let mut index = ComponentIndex::<Position>::new();
+        let crowded = Position { x: 0, y: 0 };
+        let (a, b, c) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        let loner = Entity::new(3);
+        index.insert(crowded, a);
+        index.insert(crowded, b);
+        index.insert(crowded, c);
+        index.insert(Position { x: 5, y: 5 }, loner);
+
+        assert_eq!(index.siblings(b, true).as_ref(), &[a, b, c]);
+        assert_eq!(index.siblings(b, false).as_ref(), &[a, c]);
+        assert_eq!(index.siblings(loner, true).as_ref(), &[loner]);
+        assert!(index.siblings(loner, false).is_empty());
+        assert!(index.siblings(Entity::new(4), true).is_empty());
+    }
+
+    #[test]
+    fn clean_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        for val in 0..100 {
+            index.insert(MyStruct { val }, Entity::new(0));
+        }
+        assert_eq!(index.forward().len(), 100);
+
+        index.clean();
+        assert_eq!(index.forward().len(), 1);
+        assert_eq!(index.value_of(Entity::new(0)), Some(&MyStruct { val: 99 }));
+        assert_eq!(index.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn estimated_memory_test() {
+        let mut index = ComponentIndex::<Position>::new();
+        let empty = index.estimated_memory_bytes();
+
+        let entities: Vec<Entity> = (0..400).map(Entity::new).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            let position = Position {
+                x: (i % 20) as i8,
+                y: (i / 20) as i8,
+            };
+            index.insert(position, *entity);
+        }
+        let full = index.estimated_memory_bytes();
+        assert!(full > empty);
+
+        index.remove_many(entities[10..].iter().copied());
+        // Removal alone leaves the emptied keys and their capacity behind
+        assert_eq!(index.estimated_memory_bytes(), full);
+
+        index.clean();
+        index.shrink_to_fit();
+        assert!(index.estimated_memory_bytes() < full / 10);
+        assert_eq!(index.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn drop_empty_keys_test() {
+        let mut index = ComponentIndex::<String>::new();
+        index.set_drop_empty_keys(true);
+        index.insert("permanent".to_string(), Entity::new(0));
+        let baseline = index.forward().len();
+
+        for i in 0..1000 {
+            let tag = format!("transient {}", i);
+            index.insert(tag, Entity::new(1));
+        }
+        index.remove(&Entity::new(1));
+
+        assert_eq!(index.forward().len(), baseline);
+        assert_eq!(index.key_count(), 1);
+        assert_eq!(index.lookup(&"transient 3".to_string()), Lookup::Missing);
+    }
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    enum GamePhase {
+        Menu,
+        Playing,
+    }
+
+    #[test]
+    fn state_index_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .add_resource(State::new(GamePhase::Menu))
+            .init_state_index::<GamePhase>();
+        let world = &mut app_builder.app.world;
+        let button = world.spawn((GamePhase::Menu,));
+        let player = world.spawn((GamePhase::Playing,));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<GamePhase>>()
+                .unwrap();
+            let state = app_builder.resources().get::<State<GamePhase>>().unwrap();
+            assert_eq!(index.in_current_state(&state).as_ref(), &[button]);
+            assert_eq!(index.get(&GamePhase::Playing).as_ref(), &[player]);
+        }
+
+        *app_builder.app.world.get_mut::<GamePhase>(button).unwrap() = GamePhase::Playing;
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<GamePhase>>()
+            .unwrap();
+        assert!(index.get(&GamePhase::Menu).is_empty());
+        assert_eq!(index.get(&GamePhase::Playing).as_ref(), &[player, button]);
+    }
+
+    // Deliberately breaks the Eq contract, as f32 does for NaN
+    #[derive(Debug, Clone, Copy)]
+    struct Weight(f32);
+
+    impl PartialEq for Weight {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl Eq for Weight {}
+
+    impl Hash for Weight {
+        fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+            self.0.to_bits().hash(state);
+        }
+    }
+
+    #[test]
+    fn valid_key_test() {
+        assert!(ComponentIndex::<Weight>::is_valid_key(&Weight(1.5)));
+        assert!(!ComponentIndex::<Weight>::is_valid_key(&Weight(f32::NAN)));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "FuzzyIndex")]
+    fn unstable_key_test() {
+        let mut index = ComponentIndex::<Weight>::new();
+        index.insert(Weight(f32::NAN), Entity::new(0));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Score(u32);
+
+    #[test]
+    fn get_with_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((MyStruct { val: GOOD_NUMBER }, Score(10)));
+        let b = world.spawn((MyStruct { val: GOOD_NUMBER }, Score(20)));
+        // No score, so it's skipped
+        world.spawn((MyStruct { val: GOOD_NUMBER },));
+        world.spawn((MyStruct { val: BAD_NUMBER }, Score(30)));
+        app_builder.app.update();
+
+        fn check_scores(
+            index: Res<ComponentIndex<MyStruct>>,
+            query: Query<&Score>,
+            mut found: ResMut<Vec<(Entity, u32)>>,
+        ) {
+            *found = index
+                .get_with(&MyStruct { val: GOOD_NUMBER }, &query)
+                .into_iter()
+                .map(|(entity, score)| (entity, score.0))
+                .collect();
+        }
+
+        app_builder.add_resource(Vec::<(Entity, u32)>::new());
+        app_builder.add_system(check_scores.system());
+        app_builder.app.update();
+
+        let found = app_builder.resources().get::<Vec<(Entity, u32)>>().unwrap();
+        assert_eq!(*found, vec![(a, 10), (b, 20)]);
+    }
+
+    #[test]
+    fn get_components_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let scored = world.spawn((MyStruct { val: GOOD_NUMBER }, Score(10)));
+        let unscored = world.spawn((MyStruct { val: GOOD_NUMBER },));
+        world.spawn((MyStruct { val: BAD_NUMBER }, Score(30)));
+        app_builder.app.update();
+
+        fn total_scores(
+            index: Res<ComponentIndex<MyStruct>>,
+            query: Query<&Score>,
+            mut found: ResMut<Vec<(Entity, bool, u32)>>,
+        ) {
+            let default = Score(0);
+            *found = index
+                .get_components(&MyStruct { val: GOOD_NUMBER }, &query)
+                .map(|(entity, score)| (entity, score.is_ok(), score.unwrap_or(&default).0))
+                .collect();
+        }
+
+        app_builder.add_resource(Vec::<(Entity, bool, u32)>::new());
+        app_builder.add_system(total_scores.system());
+        app_builder.app.update();
+
+        let found = app_builder
+            .resources()
+            .get::<Vec<(Entity, bool, u32)>>()
+            .unwrap();
+        assert_eq!(*found, vec![(scored, true, 10), (unscored, false, 0)]);
+    }
+
+    #[test]
+    fn pause_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let moved = world.spawn((MyStruct { val: GOOD_NUMBER },));
+        let stripped = world.spawn((MyStruct { val: GOOD_NUMBER },));
+        app_builder.app.update();
+
+        app_builder
+            .resources()
+            .get_mut::<ComponentIndex<MyStruct>>()
+            .unwrap()
+            .set_paused(true);
+        let world = &mut app_builder.app.world;
+        world.get_mut::<MyStruct>(moved).unwrap().val = BAD_NUMBER;
+        world.remove_one::<MyStruct>(stripped).unwrap();
+        let spawned = world.spawn((MyStruct { val: BAD_NUMBER },));
+        app_builder.app.update();
+
+        {
+            let mut index = app_builder
+                .resources()
+                .get_mut::<ComponentIndex<MyStruct>>()
+                .unwrap();
+            // Nothing changed while paused
+            assert_eq!(
+                index.get(&MyStruct { val: GOOD_NUMBER }).as_ref(),
+                &[moved, stripped]
+            );
+            assert!(index.get(&MyStruct { val: BAD_NUMBER }).is_empty());
+            index.set_paused(false);
+        }
+        app_builder.app.update();
+
+        let app = &app_builder.app;
+        assert_indexed(app, &MyStruct { val: GOOD_NUMBER }, &[]);
+        assert_indexed(app, &MyStruct { val: BAD_NUMBER }, &[spawned, moved]);
+        let index = app.resources.get::<ComponentIndex<MyStruct>>().unwrap();
+        assert_eq!(index.value_of(stripped), None);
+    }
+
+    #[test]
+    fn handle_index_test() {
+        use bevy::asset::HandleId;
+
+        let mut app_builder = App::build();
+        app_builder.init_handle_index::<ColorMaterial>();
+        let alive = Handle::<ColorMaterial>::weak(HandleId::random::<ColorMaterial>());
+        let dead = Handle::<ColorMaterial>::weak(HandleId::random::<ColorMaterial>());
+
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((alive.clone(),));
+        let b = world.spawn((dead.clone(),));
+        let c = world.spawn((alive.clone(),));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<Handle<ColorMaterial>>>()
+                .unwrap();
+            assert_eq!(index.get(&alive).as_ref(), &[a, c]);
+            assert_eq!(index.get(&dead).as_ref(), &[b]);
+        }
+
+        *app_builder
+            .app
+            .world
+            .get_mut::<Handle<ColorMaterial>>(c)
+            .unwrap() = dead.clone();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Handle<ColorMaterial>>>()
+            .unwrap();
+        assert_eq!(index.get(&alive).as_ref(), &[a]);
+        assert_eq!(index.get(&dead).as_ref(), &[b, c]);
     }
 
     #[test]
-    #[should_panic] // Commands don't get processed until the end of the current Stage
-    fn same_stage_modification_test() {
-        App::build()
+    fn double_remove_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let key = MyStruct { val: GOOD_NUMBER };
+        let entity = Entity::new(0);
+        index.set_consistency_policy(ConsistencyPolicy::Warn);
+
+        index.insert(key.clone(), entity);
+        index.remove(&entity);
+        index.remove(&entity);
+        assert_eq!(index.entities_len(&key), 0);
+
+        // Corrupt the forward map so that the next removal has nothing to take away
+        index.insert(key.clone(), entity);
+        index.forward.get_vec_mut(&key).unwrap().clear();
+        index.remove(&entity);
+        assert_eq!(index.entities_len(&key), 0);
+        assert_eq!(index.key_count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "was not stored under its key")]
+    fn corrupted_remove_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let key = MyStruct { val: GOOD_NUMBER };
+        let entity = Entity::new(0);
+        index.set_consistency_policy(ConsistencyPolicy::Panic);
+
+        index.insert(key.clone(), entity);
+        index.forward.get_vec_mut(&key).unwrap().clear();
+        index.remove(&entity);
+    }
+
+    fn count_runs(mut runs: ResMut<usize>) {
+        *runs += 1;
+    }
+
+    #[test]
+    fn index_changed_test() {
+        let mut app_builder = App::build();
+        app_builder
             .init_index::<MyStruct>()
-            .add_system(spawn_bad_entity.system())
-            .add_system(reform_entities.system())
-            .add_system_to_stage(stage::LAST, ensure_goodness.system())
-            .run()
+            .add_resource(0_usize)
+            .add_stage_after(
+                stage::POST_UPDATE,
+                "index_readers",
+                SystemStage::parallel().with_run_criteria(index_changed::<MyStruct>.system()),
+            )
+            .add_system_to_stage("index_readers", count_runs.system());
+        app_builder.app.update();
+        assert_eq!(*app_builder.resources().get::<usize>().unwrap(), 0);
+
+        let entity = app_builder.app.world.spawn((MyStruct { val: BAD_NUMBER },));
+        app_builder.app.update();
+        assert_eq!(*app_builder.resources().get::<usize>().unwrap(), 1);
+
+        // Idle frame
+        app_builder.app.update();
+        assert_eq!(*app_builder.resources().get::<usize>().unwrap(), 1);
+
+        // Mutated, but still under the same key
+        app_builder
+            .app
+            .world
+            .get_mut::<MyStruct>(entity)
+            .unwrap()
+            .val = BAD_NUMBER;
+        app_builder.app.update();
+        assert_eq!(*app_builder.resources().get::<usize>().unwrap(), 1);
+
+        app_builder
+            .app
+            .world
+            .get_mut::<MyStruct>(entity)
+            .unwrap()
+            .val = GOOD_NUMBER;
+        app_builder.app.update();
+        assert_eq!(*app_builder.resources().get::<usize>().unwrap(), 2);
+    }
+
+    type Voxel = [i8; 3];
+
+    fn count_origin_voxels(
+        query: Query<&Voxel>,
+        index: Res<ComponentIndex<Voxel>>,
+        mut n_found: ResMut<usize>,
+    ) {
+        index_join!(index, &[0, 0, 0], &query, |_entity, voxel| {
+            assert_eq!(voxel, &[0, 0, 0]);
+            *n_found += 1;
+        });
     }
 
     #[test]
-    #[should_panic] // Commands don't get processed until the end of the current Stage
-    fn same_stage_removal_test() {
-        App::build()
+    fn array_key_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index::<Voxel>()
+            .add_resource(0_usize)
+            .add_system(count_origin_voxels.system());
+        let world = &mut app_builder.app.world;
+        let origin = world.spawn(([0_i8, 0, 0],));
+        let above = world.spawn(([0_i8, 1, 0],));
+        app_builder.app.update();
+
+        *app_builder.app.world.get_mut::<Voxel>(above).unwrap() = [0, 0, 0];
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Voxel>>()
+            .unwrap();
+        assert_eq!(index.get(&[0, 0, 0]).as_ref(), &[origin, above]);
+        assert!(index.get(&[0, 1, 0]).is_empty());
+        assert_eq!(index.value_of(above), Some(&[0, 0, 0]));
+        // Systems in UPDATE see the index as it was at the end of the previous frame,
+        // so the moved voxel isn't found until the frame after
+        assert_eq!(*app_builder.resources().get::<usize>().unwrap(), 2);
+    }
+
+    #[test]
+    fn extend_test() {
+        let (west_only, shared, east_only) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        let mut west = ComponentIndex::<MyStruct>::new();
+        west.insert(MyStruct { val: BAD_NUMBER }, west_only);
+        west.insert(MyStruct { val: BAD_NUMBER }, shared);
+        let mut east = ComponentIndex::<MyStruct>::new();
+        east.insert(MyStruct { val: GOOD_NUMBER }, shared);
+        east.insert(MyStruct { val: GOOD_NUMBER }, east_only);
+
+        west.extend(east);
+        assert_eq!(
+            west.get(&MyStruct { val: BAD_NUMBER }).as_ref(),
+            &[west_only]
+        );
+        assert_eq!(
+            west.get(&MyStruct { val: GOOD_NUMBER }).as_ref(),
+            &[shared, east_only]
+        );
+        assert_eq!(west.value_of(shared), Some(&MyStruct { val: GOOD_NUMBER }));
+        assert_eq!(west.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn iter_live_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let survivor = world.spawn((MyStruct { val: GOOD_NUMBER },));
+        let doomed = world.spawn((MyStruct { val: BAD_NUMBER },));
+        app_builder.app.update();
+
+        // Despawn without letting the index update
+        app_builder.app.world.despawn(doomed).unwrap();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        let live: Vec<(Entity, &MyStruct)> = index.iter_live(&app_builder.app.world).collect();
+        assert_eq!(live, vec![(survivor, &MyStruct { val: GOOD_NUMBER })]);
+        assert_eq!(index.iter().count(), 2);
+    }
+
+    const OFF_MAP: Position = Position { x: -1, y: -1 };
+
+    fn clamp_to_map(position: &Position) -> Position {
+        if (0..10).contains(&position.x) && (0..10).contains(&position.y) {
+            *position
+        } else {
+            OFF_MAP
+        }
+    }
+
+    #[test]
+    fn normalized_index_test() {
+        let mut app_builder = App::build();
+        app_builder.init_normalized_index::<Position>(clamp_to_map);
+        let world = &mut app_builder.app.world;
+        let inside = world.spawn((Position { x: 3, y: 3 },));
+        let left = world.spawn((Position { x: -5, y: 3 },));
+        let below = world.spawn((Position { x: 2, y: 100 },));
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Position>>()
+            .unwrap();
+        assert_eq!(index.get(&Position { x: 3, y: 3 }).as_ref(), &[inside]);
+        assert_eq!(index.get(&OFF_MAP).as_ref(), &[left, below]);
+        // Lookups are normalized too
+        assert_eq!(
+            index.get(&Position { x: 50, y: 50 }).as_ref(),
+            &[left, below]
+        );
+        assert_eq!(index.value_of(below), Some(&OFF_MAP));
+    }
+
+    #[test]
+    fn symmetric_difference_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let (good, bad, other) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        index.insert(MyStruct { val: GOOD_NUMBER }, good);
+        index.insert(MyStruct { val: BAD_NUMBER }, bad);
+        index.insert(MyStruct { val: 7 }, other);
+
+        assert_eq!(
+            index.symmetric_difference(
+                &MyStruct { val: GOOD_NUMBER },
+                &MyStruct { val: BAD_NUMBER }
+            ),
+            vec![good, bad]
+        );
+        assert!(index
+            .symmetric_difference(
+                &MyStruct { val: GOOD_NUMBER },
+                &MyStruct { val: GOOD_NUMBER }
+            )
+            .is_empty());
+    }
+
+    fn build_shuffled_index() -> ComponentIndex<MyStruct> {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        for i in 0..50 {
+            index.insert(
+                MyStruct {
+                    val: (i * 7 % 5) as i8,
+                },
+                Entity::new(i),
+            );
+        }
+        for i in (0..50).step_by(3) {
+            index.remove(&Entity::new(i));
+        }
+        for i in (1..50).step_by(4) {
+            index.insert(MyStruct { val: (i % 3) as i8 }, Entity::new(i));
+        }
+        // Merges key 3 into 0 and key 4 into 1
+        index.map_keys(|key| MyStruct { val: key.val % 3 });
+
+        index
+    }
+
+    #[test]
+    fn deterministic_order_test() {
+        let expected = build_shuffled_index();
+        for _ in 0..10 {
+            // Each index has its own randomly seeded hasher
+            let index = build_shuffled_index();
+            for val in 0..5 {
+                let key = MyStruct { val };
+                assert_eq!(index.get(&key), expected.get(&key));
+            }
+        }
+    }
+
+    #[test]
+    fn get_mapped_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(3));
+        index.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(5));
+
+        let ids = index.get_mapped(&MyStruct { val: GOOD_NUMBER }, |entity| entity.id() * 10);
+        assert_eq!(ids, vec![30, 50]);
+        assert!(index
+            .get_mapped(&MyStruct { val: BAD_NUMBER }, |entity| entity.id())
+            .is_empty());
+    }
+
+    fn on_map(position: &Position) -> bool {
+        (0..10).contains(&position.x) && (0..10).contains(&position.y)
+    }
+
+    #[test]
+    fn validation_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<Position>();
+        app_builder
+            .resources()
+            .get_mut::<ComponentIndex<Position>>()
+            .unwrap()
+            .set_validation(on_map);
+        let world = &mut app_builder.app.world;
+        let valid = world.spawn((Position { x: 1, y: 2 },));
+        let invalid = world.spawn((Position { x: -3, y: 2 },));
+        let wanderer = world.spawn((Position { x: 9, y: 9 },));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<Position>>()
+                .unwrap();
+            assert_eq!(index.get(&Position { x: 1, y: 2 }).as_ref(), &[valid]);
+            assert!(index.get(&Position { x: -3, y: 2 }).is_empty());
+            assert_eq!(index.value_of(invalid), None);
+        }
+
+        app_builder
+            .app
+            .world
+            .get_mut::<Position>(wanderer)
+            .unwrap()
+            .x = 10;
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Position>>()
+            .unwrap();
+        assert!(index.get(&Position { x: 9, y: 9 }).is_empty());
+        assert_eq!(index.value_of(wanderer), None);
+
+        // Only the wanderer was evicted, as the invalid entity was never indexed
+        let metrics = app_builder
+            .resources()
+            .get::<IndexMetrics<Position>>()
+            .unwrap();
+        assert_eq!(metrics.removes, 1);
+    }
+
+    #[test]
+    fn empty_keys_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let (a, b) = (Entity::new(0), Entity::new(1));
+        index.insert(MyStruct { val: 1 }, a);
+        index.insert(MyStruct { val: 2 }, b);
+        assert!(index.empty_keys().is_empty());
+
+        index.insert(MyStruct { val: 3 }, a);
+        index.remove(&b);
+        let mut empty_keys: Vec<i8> = index.empty_keys().iter().map(|key| key.val).collect();
+        empty_keys.sort();
+        assert_eq!(empty_keys, vec![1, 2]);
+
+        index.clean();
+        assert!(index.empty_keys().is_empty());
+    }
+
+    fn read_index_exclusively(world: &mut World, resources: &mut Resources) {
+        let index = resources.get::<ComponentIndex<MyStruct>>().unwrap();
+        assert_eq!(index.find_inconsistency(), None);
+
+        // Every entity the world has is already indexed under its current value
+        for (component, entity) in world.query::<(&MyStruct, Entity)>() {
+            assert!(index.is_stored_as(entity, component));
+        }
+        *resources.get_mut::<usize>().unwrap() += 1;
+    }
+
+    #[test]
+    fn exclusive_system_test() {
+        let mut app_builder = App::build();
+        app_builder
             .init_index::<MyStruct>()
-            .add_system(spawn_bad_entity.system())
-            .add_system(purge_badness.system())
-            .add_system_to_stage(stage::LAST, ensure_absence_of_bad.system())
-            .run()
+            .add_resource(0_usize)
+            .add_system_to_stage(stage::LAST, read_index_exclusively.system());
+        let world = &mut app_builder.app.world;
+        let entity = world.spawn((MyStruct { val: BAD_NUMBER },));
+        world.spawn((MyStruct { val: GOOD_NUMBER },));
+        app_builder.app.update();
+
+        app_builder
+            .app
+            .world
+            .get_mut::<MyStruct>(entity)
+            .unwrap()
+            .val = GOOD_NUMBER;
+        app_builder.app.update();
+
+        assert_eq!(*app_builder.resources().get::<usize>().unwrap(), 2);
     }
 
     #[test]
-    fn earlier_stage_addition_test() {
-        App::build()
+    fn count_where_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        for i in 0..30 {
+            index.insert(MyStruct { val: (i % 7) as i8 }, Entity::new(i));
+        }
+        index.remove(&Entity::new(4));
+
+        let is_even = |key: &MyStruct| key.val % 2 == 0;
+        let manual_count = index.iter().filter(|(_, key)| is_even(key)).count();
+        assert_eq!(index.count_where(is_even), manual_count);
+        assert_eq!(index.count_where(|_| true), 29);
+        assert_eq!(index.count_where(|_| false), 0);
+    }
+
+    #[test]
+    fn borrowed_key_test() {
+        let mut index = ComponentIndex::<String>::new();
+        let (a, b) = (Entity::new(0), Entity::new(1));
+        index.insert("Goblin".to_string(), a);
+        index.insert("Goblin".to_string(), b);
+
+        assert_eq!(index.get("Goblin").as_ref(), &[a, b]);
+        assert_eq!(index.first("Goblin"), Some(a));
+        assert_eq!(index.entities_len("Goblin"), 2);
+        assert_eq!(index.lookup("Orc"), Lookup::Missing);
+        assert_eq!(index.get_mapped("Goblin", |e| e.id()), vec![0, 1]);
+        // Owned keys still work as before
+        assert_eq!(index.last(&"Goblin".to_string()), Some(b));
+
+        // Borrowed keys are normalized just like owned ones
+        index.set_normalization(|name| name.to_lowercase());
+        assert_eq!(index.get("GOBLIN").as_ref(), &[a, b]);
+        let snapshot = index.snapshot();
+        index.remove(&a);
+        assert_eq!(index.key_delta(&snapshot, "GOBLIN"), (vec![], vec![a]));
+    }
+
+    fn spawn_family(commands: &mut Commands) {
+        commands
+            .spawn((MyStruct { val: BAD_NUMBER },))
+            .with_children(|parent| {
+                parent
+                    .spawn((MyStruct { val: GOOD_NUMBER },))
+                    .with_children(|child| {
+                        child.spawn((MyStruct { val: GOOD_NUMBER },));
+                    });
+                parent.spawn((MyStruct { val: GOOD_NUMBER },));
+            });
+    }
+
+    fn despawn_parents_recursive(
+        commands: &mut Commands,
+        index: Res<ComponentIndex<MyStruct>>,
+        armed: Res<bool>,
+    ) {
+        if *armed {
+            for parent in index.get(&MyStruct { val: BAD_NUMBER }).iter() {
+                commands.despawn_recursive(*parent);
+            }
+        }
+    }
+
+    #[test]
+    fn despawn_recursive_test() {
+        let mut app_builder = App::build();
+        app_builder
             .init_index::<MyStruct>()
-            .add_system_to_stage(stage::PRE_UPDATE, spawn_deficient_entity.system())
-            .add_system(augment_entities.system())
-            .add_system_to_stage(stage::LAST, ensure_goodness.system())
-            .run()
+            .add_resource(false)
+            .add_startup_system(spawn_family.system())
+            .add_system(despawn_parents_recursive.system());
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<MyStruct>>()
+                .unwrap();
+            assert_eq!(index.entities_len(&MyStruct { val: BAD_NUMBER }), 1);
+            assert_eq!(index.entities_len(&MyStruct { val: GOOD_NUMBER }), 3);
+        }
+
+        *app_builder.resources().get_mut::<bool>().unwrap() = true;
+        app_builder.app.update();
+
+        // Children and grandchildren are evicted along with their parent
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert_eq!(index.iter().count(), 0);
+    }
+
+    #[test]
+    fn hop_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_index::<Position>()
+            .init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        world.spawn((Position { x: 0, y: 0 }, MyStruct { val: GOOD_NUMBER }));
+        world.spawn((Position { x: 0, y: 0 }, MyStruct { val: BAD_NUMBER }));
+        // Indexed by position, but missing from the other index
+        world.spawn((Position { x: 5, y: 5 },));
+        app_builder.app.update();
+
+        let positions = app_builder
+            .resources()
+            .get::<ComponentIndex<Position>>()
+            .unwrap();
+        let values = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert_eq!(
+            hop(&positions, &values, &Position { x: 0, y: 0 }),
+            Some(&MyStruct { val: GOOD_NUMBER })
+        );
+        assert_eq!(hop(&positions, &values, &Position { x: 5, y: 5 }), None);
+        assert_eq!(hop(&positions, &values, &Position { x: 9, y: 9 }), None);
+    }
+
+    #[test]
+    fn get_one_min_test() {
+        let key = MyStruct { val: GOOD_NUMBER };
+        let ids = [7, 3, 12, 5];
+        // Store the same entities in different orders, which changes `first` but not `get_one_min`
+        for rotation in 0..ids.len() {
+            let mut index = ComponentIndex::<MyStruct>::new();
+            for i in 0..ids.len() {
+                index.insert(key.clone(), Entity::new(ids[(i + rotation) % ids.len()]));
+            }
+            assert_eq!(index.get_one_min(&key), Some(Entity::new(3)));
+        }
+        assert_eq!(ComponentIndex::<MyStruct>::new().get_one_min(&key), None);
+    }
+
+    #[test]
+    fn spare_vecs_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.set_drop_empty_keys(true);
+        for i in 0..100 {
+            index.insert(MyStruct { val: 1 }, Entity::new(i));
+        }
+        let capacity = index
+            .forward()
+            .get_vec(&MyStruct { val: 1 })
+            .unwrap()
+            .capacity();
+        for i in 0..100 {
+            index.remove(&Entity::new(i));
+        }
+        assert_eq!(index.spare_vecs.len(), 1);
+
+        // The new key reuses the dropped key's allocation rather than growing a fresh one
+        index.insert(MyStruct { val: 2 }, Entity::new(0));
+        let entities = index.forward().get_vec(&MyStruct { val: 2 }).unwrap();
+        assert_eq!(entities.capacity(), capacity);
+        assert!(index.spare_vecs.is_empty());
+
+        index.set_spare_vecs_limit(0);
+        index.remove(&Entity::new(0));
+        assert!(index.spare_vecs.is_empty());
+    }
+
+    #[test]
+    fn recycled_id_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let stale = app_builder
+            .app
+            .world
+            .spawn((MyStruct { val: GOOD_NUMBER },));
+        app_builder.app.update();
+
+        // Despawn and respawn before the index gets to update, recycling the id
+        let world = &mut app_builder.app.world;
+        world.despawn(stale).unwrap();
+        let recycled = world.spawn((MyStruct { val: BAD_NUMBER },));
+        assert_eq!(recycled.id(), stale.id());
+        assert_ne!(recycled, stale);
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<MyStruct>>()
+                .unwrap();
+            let world = &app_builder.app.world;
+            assert!(index
+                .get_live(&MyStruct { val: GOOD_NUMBER }, world)
+                .is_empty());
+            assert_eq!(index.get(&MyStruct { val: GOOD_NUMBER }).as_ref(), &[stale]);
+        }
+
+        app_builder.app.update();
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap();
+        assert!(index.get(&MyStruct { val: GOOD_NUMBER }).is_empty());
+        assert_eq!(
+            index.get(&MyStruct { val: BAD_NUMBER }).as_ref(),
+            &[recycled]
+        );
+        assert_eq!(index.value_of(stale), None);
+    }
+
+    #[test]
+    fn remove_many_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        for i in 0..20 {
+            index.insert(MyStruct { val: (i % 4) as i8 }, Entity::new(i));
+        }
+
+        // Entity 100 was never indexed, so it isn't counted
+        let doomed = (0..20)
+            .filter(|i| i % 2 == 0)
+            .chain(100..101)
+            .map(Entity::new);
+        assert_eq!(index.remove_many(doomed), 10);
+
+        assert!(index.get(&MyStruct { val: 0 }).is_empty());
+        assert!(index.get(&MyStruct { val: 2 }).is_empty());
+        assert_eq!(index.entities_len(&MyStruct { val: 1 }), 5);
+        assert_eq!(index.entities_len(&MyStruct { val: 3 }), 5);
+        assert_eq!(index.value_of(Entity::new(4)), None);
+        assert_eq!(index.find_inconsistency(), None);
+    }
+
+    #[cfg(feature = "smallvec")]
+    #[test]
+    fn get_smallvec_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let entities: Vec<Entity> = (0..6).map(Entity::new).collect();
+        index.insert_batch(MyStruct { val: GOOD_NUMBER }, &entities[..2]);
+        index.insert_batch(MyStruct { val: BAD_NUMBER }, &entities[2..]);
+
+        let few = index.get_smallvec::<4, _>(&MyStruct { val: GOOD_NUMBER });
+        assert_eq!(few.as_slice(), &entities[..2]);
+        assert!(!few.spilled());
+
+        // Results beyond the inline capacity are still complete, they just move to the heap
+        let many = index.get_smallvec::<2, _>(&MyStruct { val: BAD_NUMBER });
+        assert_eq!(many.as_slice(), &entities[2..]);
+        assert!(many.spilled());
+
+        assert!(index.get_smallvec::<4, _>(&MyStruct { val: 7 }).is_empty());
+    }
+
+    #[test]
+    fn last_update_stats_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<MyStruct>();
+        let world = &mut app_builder.app.world;
+        let entities: Vec<Entity> = (0..3)
+            .map(|_| world.spawn((MyStruct { val: BAD_NUMBER },)))
+            .collect();
+        app_builder.app.update();
+
+        let world = &mut app_builder.app.world;
+        world.get_mut::<MyStruct>(entities[0]).unwrap().val = GOOD_NUMBER;
+        // Flagged as changed, but still holding the same key
+        world.get_mut::<MyStruct>(entities[1]).unwrap().val = BAD_NUMBER;
+        world.remove_one::<MyStruct>(entities[2]).unwrap();
+        world.spawn((MyStruct { val: GOOD_NUMBER },));
+        app_builder.app.update();
+
+        let stats = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap()
+            .last_update_stats();
+        assert_eq!(
+            stats,
+            UpdateStats {
+                inserted: 1,
+                removed: 1,
+                reassigned: 1,
+                skipped: 1,
+            }
+        );
+
+        app_builder.app.update();
+        let stats = app_builder
+            .resources()
+            .get::<ComponentIndex<MyStruct>>()
+            .unwrap()
+            .last_update_stats();
+        assert_eq!(stats, UpdateStats::default());
+    }
+
+    #[test]
+    fn retain_entities_test() {
+        let mut index = ComponentIndex::<Position>::new();
+        index.set_track_frame_changes(true);
+        let entities: Vec<Entity> = (0..9).map(Entity::new).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            let position = Position {
+                x: (i % 3) as i8,
+                y: (i / 3) as i8,
+            };
+            index.insert(position, *entity);
+        }
+
+        // Keep the left column, plus one entity picked out by id
+        index.retain_entities(|entity, position| position.x == 0 || entity == entities[4]);
+
+        assert_eq!(index.reverse().len(), 4);
+        assert_eq!(index.get(&Position { x: 0, y: 2 }).as_ref(), &[entities[6]]);
+        assert_eq!(index.get(&Position { x: 1, y: 1 }).as_ref(), &[entities[4]]);
+        assert!(index.get(&Position { x: 2, y: 0 }).is_empty());
+        assert_eq!(index.value_of(entities[1]), None);
+        assert_eq!(index.evicted_this_frame().len(), 5);
+        assert_eq!(index.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn apply_delta_test() {
+        let mut server = ComponentIndex::<MyStruct>::new();
+        for i in 0..6 {
+            server.insert(MyStruct { val: (i % 2) as i8 }, Entity::new(i));
+        }
+        let mut client = server.snapshot();
+
+        server.insert(MyStruct { val: GOOD_NUMBER }, Entity::new(0));
+        server.insert(MyStruct { val: 1 }, Entity::new(2));
+        server.remove(&Entity::new(5));
+        server.insert(MyStruct { val: 0 }, Entity::new(6));
+
+        let delta = server.diff(&client);
+        assert_eq!(delta.added, vec![(Entity::new(6), MyStruct { val: 0 })]);
+        assert_eq!(delta.reassigned.len(), 2);
+        assert_eq!(delta.removed, vec![(Entity::new(5), MyStruct { val: 1 })]);
+
+        client.apply_delta(delta.clone());
+        assert_eq!(client.reverse(), server.reverse());
+        assert_eq!(client.find_inconsistency(), None);
+
+        // Applying the same delta twice changes nothing
+        client.apply_delta(delta);
+        assert_eq!(client.reverse(), server.reverse());
+
+        // A delta that disagrees with what the client has is skipped rather than corrupting it
+        let conflicting = IndexDiff {
+            added: vec![(Entity::new(1), MyStruct { val: BAD_NUMBER })],
+            reassigned: vec![(Entity::new(3), MyStruct { val: 0 }, MyStruct { val: 2 })],
+            removed: vec![(Entity::new(4), MyStruct { val: 1 })],
+        };
+        client.apply_delta(conflicting);
+        assert_eq!(client.reverse(), server.reverse());
+        assert!(server.diff(&client).added.is_empty());
+    }
+
+    #[test]
+    fn replay_test() {
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.insert(MyStruct { val: 0 }, Entity::new(0));
+        index.set_recording(true);
+
+        for i in 1..10 {
+            index.insert(MyStruct { val: (i % 3) as i8 }, Entity::new(i));
+        }
+        index.insert(MyStruct { val: 5 }, Entity::new(4));
+        index.remove(&Entity::new(7));
+        index.remove_many(vec![Entity::new(1), Entity::new(2)]);
+
+        let log = index.take_log();
+        assert_eq!(log.len(), 13);
+        assert_eq!(
+            log[9],
+            IndexOp::Reassign {
+                entity: Entity::new(4),
+                from: MyStruct { val: 1 },
+                to: MyStruct { val: 5 },
+            }
+        );
+        assert!(index.take_log().is_empty());
+        assert!(index.is_recording());
+
+        // Entity 0 was indexed before recording started, so it has to be carried over separately
+        let mut replayed = ComponentIndex::<MyStruct>::new();
+        replayed.insert(MyStruct { val: 0 }, Entity::new(0));
+        replayed.replay(&log);
+        assert_eq!(replayed, index);
+    }
+
+    #[test]
+    fn key_delta_test() {
+        let watched = MyStruct { val: GOOD_NUMBER };
+        let other = MyStruct { val: BAD_NUMBER };
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let entities: Vec<Entity> = (0..5).map(Entity::new).collect();
+        for entity in entities[0..3].iter() {
+            index.insert(watched.clone(), *entity);
+        }
+        index.insert(other.clone(), entities[3]);
+        let snapshot = index.snapshot();
+
+        index.insert(other.clone(), entities[0]);
+        index.insert(watched.clone(), entities[3]);
+        index.insert(watched.clone(), entities[4]);
+        index.remove(&entities[1]);
+        // Leaving and coming back isn't a change
+        index.insert(other.clone(), entities[2]);
+        index.insert(watched.clone(), entities[2]);
+
+        assert_eq!(
+            index.key_delta(&snapshot, &watched),
+            (
+                vec![entities[3], entities[4]],
+                vec![entities[0], entities[1]]
+            )
+        );
+        assert_eq!(
+            index.key_delta(&snapshot, &other),
+            (vec![entities[0]], vec![entities[3]])
+        );
+        assert_eq!(snapshot.key_delta(&snapshot, &watched), (vec![], vec![]));
+    }
+
+    #[test]
+    fn fixed_timestep_test() {
+        let mut app_builder = App::build();
+        // Time never advances on its own here, so ticks are driven by hand
+        app_builder
+            .add_resource(Time::default())
+            .add_resource(IndexTimestep::new(1.0))
+            .init_index_fixed::<MyStruct>();
+        let entity = app_builder.app.world.spawn((MyStruct { val: 0 },));
+        let doomed = app_builder.app.world.spawn((MyStruct { val: 5 },));
+        app_builder.app.update();
+        assert_indexed(&app_builder.app, &MyStruct { val: 0 }, &[entity]);
+
+        // Half a step: the changes are made, but no tick is due yet
+        app_builder
+            .app
+            .world
+            .get_mut::<MyStruct>(entity)
+            .unwrap()
+            .val = 1;
+        app_builder.app.world.despawn(doomed).unwrap();
+        advance_index_time(&mut app_builder, 0.5);
+        app_builder.app.update();
+        assert_indexed(&app_builder.app, &MyStruct { val: 0 }, &[entity]);
+        assert_indexed(&app_builder.app, &MyStruct { val: 5 }, &[doomed]);
+
+        // Nothing changes on the tick frame itself, yet the tick still catches up on both changes
+        advance_index_time(&mut app_builder, 0.5);
+        app_builder.app.update();
+        assert_indexed(&app_builder.app, &MyStruct { val: 0 }, &[]);
+        assert_indexed(&app_builder.app, &MyStruct { val: 1 }, &[entity]);
+        assert_indexed(&app_builder.app, &MyStruct { val: 5 }, &[]);
+    }
+
+    fn advance_index_time(app_builder: &mut AppBuilder, seconds: f64) {
+        app_builder
+            .resources()
+            .get_mut::<IndexTimestep>()
+            .unwrap()
+            .advance(seconds);
+    }
+
+    #[test]
+    fn insert_batch_test() {
+        let good = MyStruct { val: GOOD_NUMBER };
+        let bad = MyStruct { val: BAD_NUMBER };
+        let mut index = ComponentIndex::<MyStruct>::new();
+        index.set_track_frame_changes(true);
+        let entities: Vec<Entity> = (0..6).map(Entity::new).collect();
+        index.insert(good.clone(), entities[0]);
+        index.insert(good.clone(), entities[1]);
+        index.insert(bad.clone(), entities[2]);
+        index.insert(bad.clone(), entities[3]);
+
+        // Entity 1 is already stored under the key, and entity 4 is listed twice
+        let formation = [
+            entities[4],
+            entities[2],
+            entities[1],
+            entities[4],
+            entities[5],
+        ];
+        index.insert_batch(good.clone(), &formation);
+
+        assert_eq!(
+            index.get(&good).as_ref(),
+            &[
+                entities[0],
+                entities[4],
+                entities[2],
+                entities[1],
+                entities[5]
+            ]
+        );
+        assert_eq!(index.get(&bad).as_ref(), &[entities[3]]);
+        for entity in formation.iter() {
+            assert_eq!(index.value_of(*entity), Some(&good));
+        }
+        assert_eq!(index.reassigned_this_frame(), &[entities[2]]);
+        assert_eq!(index.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn partition_test() {
+        let mut index = ComponentIndex::<Position>::new();
+        for (i, x) in (-3..3).enumerate() {
+            index.insert(Position { x, y: 0 }, Entity::new(i as u32));
+            index.insert(Position { x, y: 1 }, Entity::new(i as u32 + 10));
+        }
+
+        let (left, right) = index.partition(|position| position.x < 0);
+
+        assert_eq!(left.reverse().len(), 6);
+        assert_eq!(right.reverse().len(), 6);
+        assert_eq!(
+            left.get(&Position { x: -3, y: 1 }).as_ref(),
+            &[Entity::new(10)]
+        );
+        assert!(left.get(&Position { x: 0, y: 0 }).is_empty());
+        assert_eq!(
+            right.get(&Position { x: 0, y: 0 }).as_ref(),
+            &[Entity::new(3)]
+        );
+        assert_eq!(
+            right.value_of(Entity::new(15)),
+            Some(&Position { x: 2, y: 1 })
+        );
+
+        for (entity, position) in left.iter() {
+            assert!(position.x < 0);
+            assert!(!right.is_indexed(entity));
+        }
+        assert_eq!(left.find_inconsistency(), None);
+        assert_eq!(right.find_inconsistency(), None);
+    }
+
+    #[test]
+    fn frame_changes_tracking_test() {
+        let good = MyStruct { val: GOOD_NUMBER };
+        let bad = MyStruct { val: BAD_NUMBER };
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let entity = Entity::new(0);
+
+        // Nothing ever begins a frame for a standalone index, so it doesn't record the changes of one
+        for _ in 0..100 {
+            index.insert(good.clone(), entity);
+            index.insert(bad.clone(), entity);
+        }
+        assert!(index.added_this_frame().is_empty());
+        assert!(index.reassigned_this_frame().is_empty());
+        assert!(index.changed_this_frame());
+
+        index.set_track_frame_changes(true);
+        for _ in 0..100 {
+            index.insert(good.clone(), entity);
+            index.insert(bad.clone(), entity);
+        }
+        assert_eq!(index.reassigned_this_frame(), &[entity]);
+    }
+
+    #[test]
+    fn remove_keeps_other_keys_test() {
+        // Removing an entity used to retain only its own key's entries, wiping every other key
+        let mut index = ComponentIndex::<MyStruct>::new();
+        let (a, b, c) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        index.insert(MyStruct { val: BAD_NUMBER }, a);
+        index.insert(MyStruct { val: BAD_NUMBER }, b);
+        index.insert(MyStruct { val: GOOD_NUMBER }, c);
+
+        index.remove(&a);
+        assert_eq!(index.get(&MyStruct { val: BAD_NUMBER }).as_ref(), &[b]);
+        assert_eq!(index.get(&MyStruct { val: GOOD_NUMBER }).as_ref(), &[c]);
+        assert_eq!(index.value_of(a), None);
+        assert_eq!(index.find_inconsistency(), None);
     }
 
     // FIXME: add test to catch delayed index updating with naive approach
@@ -0,0 +1,30 @@
+use std::hash::Hash;
+
+/// A form of the key `T` that lookups such as `ComponentIndex::get` accept, like the `Q` of `HashMap::get`.
+/// Lookups hash the borrowed form as is, unless the index normalizes its keys:
+/// only then is an owned key built with `canonical_key`, so that it can be normalized.
+///
+/// Every key type is a lookup key for itself, and `str` is one for `String`.
+/// Implement it for the inner type of a newtype key to look the newtype up by its contents.
+pub trait LookupKey<T>: Hash + Eq {
+    /// Builds the key this lookup refers to and normalizes it with `canonicalize`
+    fn canonical_key(&self, canonicalize: fn(&T) -> T) -> T;
+}
+
+impl<T: Hash + Eq> LookupKey<T> for T {
+    fn canonical_key(&self, canonicalize: fn(&T) -> T) -> T {
+        canonicalize(self)
+    }
+}
+
+impl LookupKey<String> for str {
+    fn canonical_key(&self, canonicalize: fn(&String) -> String) -> String {
+        canonicalize(&self.to_owned())
+    }
+}
+
+impl<A: Hash + Eq + Clone> LookupKey<Vec<A>> for [A] {
+    fn canonical_key(&self, canonicalize: fn(&Vec<A>) -> Vec<A>) -> Vec<A> {
+        canonicalize(&self.to_vec())
+    }
+}
@@ -0,0 +1,172 @@
+use crate::removal::iter_removed;
+use crate::{IndexKey, IndexRead};
+use bevy::prelude::*;
+use multimap::MultiMap;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// An index that only retains the `capacity` most recently updated entities,
+/// such as a cache of recently visible entities.
+/// Once over capacity, the entity that was updated the longest ago is evicted.
+#[derive(Debug)]
+pub struct LruIndex<T: Hash + Eq> {
+    capacity: usize,
+    forward: MultiMap<T, Entity>,
+    // Each entity's key, along with the tick of its last update
+    reverse: HashMap<Entity, (T, u64)>,
+    // Entities ordered from least to most recently updated
+    recency: BTreeMap<u64, Entity>,
+    tick: u64,
+}
+
+impl<T: Hash + Eq> LruIndex<T> {
+    pub fn new(capacity: usize) -> Self {
+        LruIndex::<T> {
+            capacity,
+            forward: MultiMap::new(),
+            reverse: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    pub fn get(&self, key: &T) -> Cow<'_, [Entity]> {
+        match self.forward.get_vec(key) {
+            Some(e) => Cow::from(e),
+            None => Cow::from(Vec::new()),
+        }
+    }
+
+    pub fn value_of(&self, entity: Entity) -> Option<&T> {
+        self.reverse.get(&entity).map(|(key, _)| key)
+    }
+
+    /// The number of entities currently retained
+    pub fn len(&self) -> usize {
+        self.reverse.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reverse.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the number of entities retained, immediately evicting the least recently updated ones if needed
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict_excess();
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) {
+        if let Some((old_key, last_tick)) = self.reverse.remove(entity) {
+            self.recency.remove(&last_tick);
+            if let Some(entities) = self.forward.get_vec_mut(&old_key) {
+                entities.retain(|e| e != entity);
+                if entities.is_empty() {
+                    self.forward.remove(&old_key);
+                }
+            }
+        }
+    }
+
+    fn evict_excess(&mut self) {
+        while self.reverse.len() > self.capacity {
+            let oldest = match self.recency.values().next() {
+                Some(entity) => *entity,
+                None => break,
+            };
+            self.remove(&oldest);
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> LruIndex<T> {
+    /// Stores `entity` under `key` as the most recently updated entity
+    pub(crate) fn insert(&mut self, key: T, entity: Entity) {
+        self.remove(&entity);
+
+        self.tick += 1;
+        self.forward.insert(key.clone(), entity);
+        self.reverse.insert(entity, (key, self.tick));
+        self.recency.insert(self.tick, entity);
+        self.evict_excess();
+    }
+}
+
+impl<T: Hash + Eq> IndexRead<T> for LruIndex<T> {
+    fn get(&self, key: &T) -> Cow<'_, [Entity]> {
+        LruIndex::get(self, key)
+    }
+
+    fn len(&self) -> usize {
+        LruIndex::len(self)
+    }
+}
+
+pub(crate) fn update_lru_index<T: IndexKey>(
+    mut index: ResMut<LruIndex<T>>,
+    query: Query<(&T, Entity)>,
+    changed_query: Query<(&T, Entity), Changed<T>>,
+) {
+    for entity in iter_removed::<T, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    // Every change counts as a use, even if the key stayed the same
+    for (component, entity) in changed_query.iter() {
+        index.insert(component.clone(), entity);
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct Visible(bool);
+
+    #[test]
+    fn lru_eviction_test() {
+        let mut index = LruIndex::<u8>::new(3);
+        let entities: Vec<Entity> = (0..5).map(Entity::new).collect();
+        for entity in entities.iter() {
+            index.insert(0, *entity);
+        }
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.get(&0).as_ref(), &entities[2..]);
+
+        // Touching an entity protects it from the next eviction
+        index.insert(1, entities[2]);
+        index.insert(0, entities[0]);
+        assert_eq!(index.value_of(entities[3]), None);
+        assert_eq!(index.value_of(entities[2]), Some(&1));
+
+        index.set_capacity(1);
+        assert_eq!(index.get(&0).as_ref(), &[entities[0]]);
+        assert!(index.get(&1).is_empty());
+    }
+
+    #[test]
+    fn lru_update_test() {
+        let mut app_builder = App::build();
+        app_builder.init_lru_index::<Visible>(2);
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((Visible(true),));
+        let b = world.spawn((Visible(true),));
+        app_builder.app.update();
+
+        let c = app_builder.app.world.spawn((Visible(true),));
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<LruIndex<Visible>>().unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.value_of(a), None);
+        assert_eq!(index.get(&Visible(true)).as_ref(), &[b, c]);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::removal::iter_removed;
+use bevy::prelude::*;
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// A component that holds a list of other entities, such as `Children` or the members of a squad
+pub trait EntityList: Component {
+    fn members(&self) -> &[Entity];
+}
+
+impl EntityList for Children {
+    fn members(&self) -> &[Entity] {
+        &self[..]
+    }
+}
+
+/// Maps each entity listed in an `EntityList` component `C` back to the entity owning that list,
+/// answering "which group is this entity a member of".
+/// An entity listed by several owners at once is attributed to whichever owner's list changed last.
+#[derive(Debug)]
+pub struct MembershipIndex<C: EntityList> {
+    owners: HashMap<Entity, Entity>,
+    // The members of each owner as of its last update, so they can be released when the list changes
+    members: HashMap<Entity, Vec<Entity>>,
+    phantom: PhantomData<C>,
+}
+
+impl<C: EntityList> MembershipIndex<C> {
+    pub fn new() -> Self {
+        MembershipIndex::<C>::default()
+    }
+
+    /// The entity whose list `member` is part of
+    pub fn owner_of(&self, member: Entity) -> Option<Entity> {
+        self.owners.get(&member).copied()
+    }
+
+    /// The members of `owner`, as of the last time the index was updated
+    pub fn members_of(&self, owner: Entity) -> &[Entity] {
+        match self.members.get(&owner) {
+            Some(e) => e,
+            None => &[],
+        }
+    }
+
+    pub(crate) fn insert(&mut self, owner: Entity, members: &[Entity]) {
+        self.remove(&owner);
+
+        for member in members.iter() {
+            self.owners.insert(*member, owner);
+        }
+        self.members.insert(owner, members.to_vec());
+    }
+
+    pub(crate) fn remove(&mut self, owner: &Entity) {
+        if let Some(old_members) = self.members.remove(owner) {
+            for member in old_members.iter() {
+                // The member may have already been claimed by the group it moved to
+                if self.owners.get(member) == Some(owner) {
+                    self.owners.remove(member);
+                }
+            }
+        }
+    }
+}
+
+impl<C: EntityList> Default for MembershipIndex<C> {
+    fn default() -> Self {
+        MembershipIndex::<C> {
+            owners: HashMap::new(),
+            members: HashMap::new(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+pub(crate) fn update_membership_index<C: EntityList>(
+    mut index: ResMut<MembershipIndex<C>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    // Despawning an owner (or stripping its list) frees all of its members
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (list, entity) in changed_query.iter() {
+        index.insert(entity, list.members());
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    struct Members(Vec<Entity>);
+
+    impl EntityList for Members {
+        fn members(&self) -> &[Entity] {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn membership_update_test() {
+        let mut app_builder = App::build();
+        app_builder.init_membership_index::<Members>();
+        let world = &mut app_builder.app.world;
+        let units: Vec<Entity> = (0..4).map(|_| world.spawn(())).collect();
+        let red = world.spawn((Members(vec![units[0], units[1]]),));
+        let blue = world.spawn((Members(vec![units[2], units[3]]),));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<MembershipIndex<Members>>()
+                .unwrap();
+            assert_eq!(index.owner_of(units[1]), Some(red));
+            assert_eq!(index.owner_of(units[2]), Some(blue));
+            assert_eq!(index.owner_of(red), None);
+        }
+
+        // Unit 1 defects to blue, while unit 3 leaves blue without joining anyone
+        let world = &mut app_builder.app.world;
+        world.get_mut::<Members>(red).unwrap().0 = vec![units[0]];
+        world.get_mut::<Members>(blue).unwrap().0 = vec![units[2], units[1]];
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<MembershipIndex<Members>>()
+                .unwrap();
+            assert_eq!(index.owner_of(units[0]), Some(red));
+            assert_eq!(index.owner_of(units[1]), Some(blue));
+            assert_eq!(index.owner_of(units[3]), None);
+            assert_eq!(index.members_of(blue), &[units[2], units[1]]);
+        }
+
+        app_builder.app.world.despawn(red).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<MembershipIndex<Members>>()
+            .unwrap();
+        assert_eq!(index.owner_of(units[0]), None);
+        assert!(index.members_of(red).is_empty());
+        assert_eq!(index.owner_of(units[1]), Some(blue));
+    }
+}
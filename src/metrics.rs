@@ -0,0 +1,59 @@
+use crate::IndexKey;
+use bevy::prelude::*;
+
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// Profiling counters for the maintenance of a `ComponentIndex<T>`, accumulated over the current frame
+#[derive(Debug)]
+pub struct IndexMetrics<T: IndexKey> {
+    /// Number of entities (re)inserted into the index
+    pub inserts: usize,
+    /// Number of entities evicted because their component was removed
+    pub removes: usize,
+    /// Time spent in the index's update systems
+    pub time_spent: Duration,
+    phantom: PhantomData<T>,
+}
+
+impl<T: IndexKey> Default for IndexMetrics<T> {
+    fn default() -> Self {
+        IndexMetrics::<T> {
+            inserts: 0,
+            removes: 0,
+            time_spent: Duration::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: IndexKey> IndexMetrics<T> {
+    // Counters saturate rather than wrap, so runaway churn can't make them read as small again
+    pub(crate) fn record_insert(&mut self) {
+        self.inserts = self.inserts.saturating_add(1);
+    }
+
+    pub(crate) fn record_remove(&mut self) {
+        self.removes = self.removes.saturating_add(1);
+    }
+}
+
+pub(crate) fn reset_index_metrics<T: IndexKey>(mut metrics: ResMut<IndexMetrics<T>>) {
+    *metrics = IndexMetrics::default();
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn saturating_metrics_test() {
+        let mut metrics = IndexMetrics::<u8>::default();
+        metrics.inserts = usize::MAX;
+        metrics.record_insert();
+        metrics.record_remove();
+
+        assert_eq!(metrics.inserts, usize::MAX);
+        assert_eq!(metrics.removes, 1);
+    }
+}
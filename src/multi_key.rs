@@ -0,0 +1,167 @@
+use crate::removal::iter_removed;
+use crate::{symmetric_difference, IndexRead};
+use bevy::prelude::*;
+use multimap::MultiMap;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// An index where each entity can be stored under several keys at once,
+/// as produced by a projection from the component `C` to a `Vec<K>`
+#[derive(Debug, PartialEq, Eq)]
+pub struct MultiKeyIndex<K: Hash + Eq> {
+    forward: MultiMap<K, Entity>,
+    reverse: HashMap<Entity, HashSet<K>>,
+}
+
+impl<K: Hash + Eq> MultiKeyIndex<K> {
+    pub fn get(&self, key: &K) -> Cow<'_, [Entity]> {
+        match self.forward.get_vec(key) {
+            Some(e) => Cow::from(e),
+            None => Cow::from(Vec::new()),
+        }
+    }
+
+    pub fn new() -> Self {
+        MultiKeyIndex::<K>::default()
+    }
+
+    /// Entities stored under exactly one of `key_a` and `key_b`
+    pub fn symmetric_difference(&self, key_a: &K, key_b: &K) -> Vec<Entity> {
+        symmetric_difference(&self.get(key_a), &self.get(key_b))
+    }
+
+    pub fn keys_of(&self, entity: Entity) -> Option<&HashSet<K>> {
+        self.reverse.get(&entity)
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) {
+        if let Some(old_keys) = self.reverse.remove(entity) {
+            // Drop the entity from every key it was stored under
+            for key in old_keys.iter() {
+                if let Some(entities) = self.forward.get_vec_mut(key) {
+                    entities.retain(|e| e != entity);
+                }
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> MultiKeyIndex<K> {
+    pub(crate) fn insert(&mut self, keys: Vec<K>, entity: Entity) {
+        self.remove(&entity);
+
+        let keys: HashSet<K> = keys.into_iter().collect();
+        for key in keys.iter() {
+            self.forward.insert(key.clone(), entity);
+        }
+        self.reverse.insert(entity, keys);
+    }
+}
+
+impl<K: Hash + Eq> IndexRead<K> for MultiKeyIndex<K> {
+    fn get(&self, key: &K) -> Cow<'_, [Entity]> {
+        MultiKeyIndex::get(self, key)
+    }
+
+    /// The number of entities in the index, each counted once however many keys it has
+    fn len(&self) -> usize {
+        self.reverse.len()
+    }
+}
+
+impl<K: Hash + Eq> Default for MultiKeyIndex<K> {
+    fn default() -> Self {
+        MultiKeyIndex::<K> {
+            forward: MultiMap::new(),
+            reverse: HashMap::new(),
+        }
+    }
+}
+
+/// The projection used to compute the keys of a `C` for its `MultiKeyIndex<K>`
+pub struct KeyProjection<C, K> {
+    pub(crate) project: fn(&C) -> Vec<K>,
+}
+
+pub(crate) fn update_multi_key_index<C: Component, K: Hash + Eq + Clone + Send + Sync + 'static>(
+    mut index: ResMut<MultiKeyIndex<K>>,
+    projection: Res<KeyProjection<C, K>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        index.insert((projection.project)(component), entity);
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Tags(Vec<u8>);
+
+    fn project_tags(tags: &Tags) -> Vec<u8> {
+        tags.0.clone()
+    }
+
+    #[test]
+    fn reprojection_test() {
+        let mut app_builder = App::build();
+        app_builder.init_multi_key_index::<Tags, u8>(project_tags);
+        let entity = app_builder.app.world.spawn((Tags(vec![1, 2, 3]),));
+        app_builder.app.update();
+
+        {
+            let index = app_builder.resources().get::<MultiKeyIndex<u8>>().unwrap();
+            for key in 1..=3 {
+                assert_eq!(index.get(&key).as_ref(), &[entity]);
+            }
+            assert_eq!(index.keys_of(entity).unwrap().len(), 3);
+        }
+
+        app_builder.app.world.get_mut::<Tags>(entity).unwrap().0 = vec![2, 3];
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<MultiKeyIndex<u8>>().unwrap();
+        assert!(index.get(&1).is_empty());
+        assert_eq!(index.get(&2).as_ref(), &[entity]);
+        assert_eq!(index.get(&3).as_ref(), &[entity]);
+        assert_eq!(index.keys_of(entity).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn multi_key_removal_test() {
+        let mut index = MultiKeyIndex::<u8>::new();
+        let entity = Entity::new(0);
+        let other = Entity::new(1);
+
+        index.insert(vec![1, 2, 3], entity);
+        index.insert(vec![3], other);
+        index.remove(&entity);
+
+        assert!(index.get(&1).is_empty());
+        assert!(index.get(&2).is_empty());
+        assert_eq!(index.get(&3).as_ref(), &[other]);
+        assert_eq!(index.keys_of(entity), None);
+    }
+
+    #[test]
+    fn multi_key_symmetric_difference_test() {
+        let mut index = MultiKeyIndex::<u8>::new();
+        let (both, only_1, only_2) = (Entity::new(0), Entity::new(1), Entity::new(2));
+        index.insert(vec![1, 2], both);
+        index.insert(vec![1], only_1);
+        index.insert(vec![2, 3], only_2);
+
+        assert_eq!(index.symmetric_difference(&1, &2), vec![only_1, only_2]);
+        assert!(index.symmetric_difference(&1, &1).is_empty());
+    }
+}
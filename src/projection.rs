@@ -0,0 +1,154 @@
+use crate::removal::iter_removed;
+use crate::{ComponentIndex, IndexKey};
+use bevy::prelude::*;
+
+use std::any::Any;
+use std::marker::PhantomData;
+
+/// Several `ComponentIndex`es fed from the same component `C`,
+/// each storing entities under a different projection of it.
+/// All of them are maintained by a single change detection pass over `C`.
+/// Each projection must produce a distinct key type, which is used to look up its index.
+pub struct ProjectionIndexes<C> {
+    projections: Vec<Box<dyn ProjectedIndex<C>>>,
+}
+
+impl<C: Component> ProjectionIndexes<C> {
+    /// The index fed by the projection that produces `K`s
+    pub fn index<K: IndexKey>(&self) -> Option<&ComponentIndex<K>> {
+        self.projections
+            .iter()
+            .find_map(|p| p.as_any().downcast_ref::<Projection<C, K>>())
+            .map(|p| &p.index)
+    }
+
+    pub(crate) fn push<K: IndexKey>(&mut self, project: fn(&C) -> K) {
+        assert!(
+            self.index::<K>().is_none(),
+            "Each projection of a component must produce a distinct key type"
+        );
+
+        self.projections.push(Box::new(Projection::<C, K> {
+            project,
+            index: ComponentIndex::new(),
+        }));
+    }
+}
+
+impl<C> Default for ProjectionIndexes<C> {
+    fn default() -> Self {
+        ProjectionIndexes::<C> {
+            projections: Vec::new(),
+        }
+    }
+}
+
+// Lets indexes over different key types be stored and updated side by side
+trait ProjectedIndex<C>: Send + Sync {
+    fn update(&mut self, component: &C, entity: Entity);
+    fn remove(&mut self, entity: &Entity);
+    fn as_any(&self) -> &dyn Any;
+}
+
+struct Projection<C, K: IndexKey> {
+    project: fn(&C) -> K,
+    index: ComponentIndex<K>,
+}
+
+impl<C: Component, K: IndexKey> ProjectedIndex<C> for Projection<C, K> {
+    fn update(&mut self, component: &C, entity: Entity) {
+        let key = (self.project)(component);
+        if !self.index.is_stored_as(entity, &key) {
+            self.index.insert(key, entity);
+        }
+    }
+
+    fn remove(&mut self, entity: &Entity) {
+        self.index.remove(entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Adds projections to the `ProjectionIndexes<C>` created by `init_multi_projection`
+pub struct MultiProjectionBuilder<'a, C> {
+    pub(crate) app_builder: &'a mut AppBuilder,
+    pub(crate) phantom: PhantomData<C>,
+}
+
+impl<'a, C: Component> MultiProjectionBuilder<'a, C> {
+    /// Adds an index storing each entity under `project` applied to its `C`
+    pub fn with<K: IndexKey>(self, project: fn(&C) -> K) -> Self {
+        self.app_builder
+            .resources()
+            .get_mut::<ProjectionIndexes<C>>()
+            .unwrap()
+            .push(project);
+
+        self
+    }
+}
+
+pub(crate) fn update_projection_indexes<C: Component>(
+    mut indexes: ResMut<ProjectionIndexes<C>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        for projection in indexes.projections.iter_mut() {
+            projection.remove(&entity);
+        }
+    }
+
+    for (component, entity) in changed_query.iter() {
+        for projection in indexes.projections.iter_mut() {
+            projection.update(component, entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    struct Spot {
+        x: i8,
+        y: i8,
+    }
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct Column(i8);
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct Row(i8);
+
+    #[test]
+    fn multi_projection_test() {
+        let mut app_builder = App::build();
+        app_builder
+            .init_multi_projection::<Spot>()
+            .with(|spot| Column(spot.x))
+            .with(|spot| Row(spot.y));
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((Spot { x: 0, y: 0 },));
+        let b = world.spawn((Spot { x: 0, y: 1 },));
+        app_builder.app.update();
+
+        app_builder.app.world.get_mut::<Spot>(a).unwrap().y = 1;
+        app_builder.app.update();
+
+        let indexes = app_builder
+            .resources()
+            .get::<ProjectionIndexes<Spot>>()
+            .unwrap();
+        let columns = indexes.index::<Column>().unwrap();
+        let rows = indexes.index::<Row>().unwrap();
+        assert_eq!(columns.get(&Column(0)).as_ref(), &[a, b]);
+        assert!(rows.get(&Row(0)).is_empty());
+        assert_eq!(rows.get(&Row(1)).as_ref(), &[b, a]);
+        assert!(indexes.index::<u8>().is_none());
+    }
+}
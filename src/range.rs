@@ -0,0 +1,337 @@
+use crate::removal::iter_removed;
+use crate::IndexRead;
+use bevy::prelude::*;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+
+/// An index over an ordered key, supporting range queries.
+/// Keys are dropped as soon as their last entity leaves, so every stored key is non-empty.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RangeIndex<T: Ord> {
+    forward: BTreeMap<T, Vec<Entity>>,
+    reverse: HashMap<Entity, T>,
+}
+
+impl<T: Ord> RangeIndex<T> {
+    pub fn new() -> Self {
+        RangeIndex::<T>::default()
+    }
+
+    pub fn get(&self, key: &T) -> &[Entity] {
+        match self.forward.get(key) {
+            Some(e) => e,
+            None => &[],
+        }
+    }
+
+    /// All entities whose key lies within `range`, in key order
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> Vec<Entity> {
+        if is_empty_range(range.start_bound(), range.end_bound()) {
+            return Vec::new();
+        }
+
+        self.forward
+            .range(range)
+            .flat_map(|(_, entities)| entities.iter().copied())
+            .collect()
+    }
+
+    /// Calls `f` with each entity whose key lies within `range`, along with that key, in key order.
+    /// Unlike `range`, nothing is collected along the way.
+    pub fn for_each_in_range<R: RangeBounds<T>, F: FnMut(&T, Entity)>(&self, range: R, mut f: F) {
+        if is_empty_range(range.start_bound(), range.end_bound()) {
+            return;
+        }
+
+        for (key, entities) in self.forward.range(range) {
+            for entity in entities.iter() {
+                f(key, *entity);
+            }
+        }
+    }
+
+    pub fn value_of(&self, entity: Entity) -> Option<&T> {
+        self.reverse.get(&entity)
+    }
+
+    /// The smallest key that currently has at least one entity
+    pub fn min_key(&self) -> Option<&T> {
+        self.forward.keys().next()
+    }
+
+    /// The largest key that currently has at least one entity
+    pub fn max_key(&self) -> Option<&T> {
+        self.forward.keys().next_back()
+    }
+
+    /// The entities under each of the `n` largest keys, largest first, as for a leaderboard
+    pub fn top_n(&self, n: usize) -> Vec<(&T, &[Entity])> {
+        self.forward
+            .iter()
+            .rev()
+            .take(n)
+            .map(|(key, entities)| (key, entities.as_slice()))
+            .collect()
+    }
+
+    /// The entities under each of the `n` smallest keys, smallest first
+    pub fn bottom_n(&self, n: usize) -> Vec<(&T, &[Entity])> {
+        self.forward
+            .iter()
+            .take(n)
+            .map(|(key, entities)| (key, entities.as_slice()))
+            .collect()
+    }
+
+    pub fn remove(&mut self, entity: &Entity) {
+        if let Some(old_key) = self.reverse.remove(entity) {
+            if let Some(entities) = self.forward.get_mut(&old_key) {
+                entities.retain(|e| e != entity);
+                if entities.is_empty() {
+                    self.forward.remove(&old_key);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Ord + Clone> RangeIndex<T> {
+    pub fn insert(&mut self, key: T, entity: Entity) {
+        self.remove(&entity);
+
+        self.forward
+            .entry(key.clone())
+            .or_insert_with(Vec::new)
+            .push(entity);
+        self.reverse.insert(entity, key);
+    }
+}
+
+impl<T: Ord + Debug> RangeIndex<T> {
+    /// Lists each key with its number of entities, one per line, in key order
+    pub fn dump(&self) -> String {
+        self.forward
+            .iter()
+            .map(|(key, entities)| format!("{:?}: {}", key, entities.len()))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl<T: Ord> IndexRead<T> for RangeIndex<T> {
+    fn get(&self, key: &T) -> Cow<'_, [Entity]> {
+        Cow::from(RangeIndex::get(self, key))
+    }
+
+    fn len(&self) -> usize {
+        self.reverse.len()
+    }
+}
+
+impl<T: Ord> Default for RangeIndex<T> {
+    fn default() -> Self {
+        RangeIndex::<T> {
+            forward: BTreeMap::new(),
+            reverse: HashMap::new(),
+        }
+    }
+}
+
+/// Whether the bounds describe an empty or inverted range, which `BTreeMap::range` panics on
+pub(crate) fn is_empty_range<T: Ord>(start: Bound<&T>, end: Bound<&T>) -> bool {
+    match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s), Bound::Excluded(e))
+        | (Bound::Excluded(s), Bound::Included(e))
+        | (Bound::Excluded(s), Bound::Excluded(e)) => s >= e,
+        _ => false,
+    }
+}
+
+pub(crate) fn update_range_index<T: Component + Ord + Clone>(
+    mut index: ResMut<RangeIndex<T>>,
+    query: Query<(&T, Entity)>,
+    changed_query: Query<(&T, Entity), Changed<T>>,
+) {
+    for entity in iter_removed::<T, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        if index.value_of(entity) != Some(component) {
+            index.insert(component.clone(), entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct Score(u32);
+
+    #[test]
+    fn bounds_test() {
+        let mut index = RangeIndex::<Score>::new();
+        assert_eq!(index.min_key(), None);
+        assert_eq!(index.max_key(), None);
+
+        let (low, low_too, mid, high) = (
+            Entity::new(0),
+            Entity::new(1),
+            Entity::new(2),
+            Entity::new(3),
+        );
+        index.insert(Score(5), mid);
+        index.insert(Score(1), low);
+        index.insert(Score(1), low_too);
+        index.insert(Score(9), high);
+        assert_eq!(index.min_key(), Some(&Score(1)));
+        assert_eq!(index.max_key(), Some(&Score(9)));
+
+        // The minimum only shifts once its last entity leaves
+        index.remove(&low);
+        assert_eq!(index.min_key(), Some(&Score(1)));
+        index.insert(Score(6), low_too);
+        assert_eq!(index.min_key(), Some(&Score(5)));
+
+        index.remove(&high);
+        assert_eq!(index.max_key(), Some(&Score(6)));
+    }
+
+    #[test]
+    fn dump_test() {
+        let mut index = RangeIndex::<Score>::new();
+        for (i, score) in [30, 10, 20, 10, 30, 30].iter().enumerate() {
+            index.insert(Score(*score), Entity::new(i as u32));
+        }
+        index.remove(&Entity::new(2));
+
+        assert_eq!(index.dump(), "Score(10): 2\nScore(30): 3");
+    }
+
+    #[test]
+    fn top_n_test() {
+        let mut index = RangeIndex::<Score>::new();
+        let entities: Vec<Entity> = (0..6).map(Entity::new).collect();
+        for (entity, score) in entities.iter().zip([50, 20, 90, 50, 10, 70].iter()) {
+            index.insert(Score(*score), *entity);
+        }
+
+        // Tied scores share a group, which only counts once towards n
+        assert_eq!(
+            index.top_n(3),
+            vec![
+                (&Score(90), &[entities[2]][..]),
+                (&Score(70), &[entities[5]][..]),
+                (&Score(50), &[entities[0], entities[3]][..]),
+            ]
+        );
+        assert_eq!(
+            index.bottom_n(2),
+            vec![
+                (&Score(10), &[entities[4]][..]),
+                (&Score(20), &[entities[1]][..]),
+            ]
+        );
+        assert_eq!(index.top_n(10).len(), 5);
+        assert!(index.bottom_n(0).is_empty());
+    }
+
+    #[test]
+    fn range_test() {
+        let mut index = RangeIndex::<Score>::new();
+        let entities: Vec<Entity> = (0..5).map(Entity::new).collect();
+        for (i, entity) in entities.iter().enumerate() {
+            index.insert(Score(i as u32 * 10), *entity);
+        }
+
+        assert_eq!(index.range(Score(10)..Score(30)), entities[1..3].to_vec());
+        assert_eq!(index.range(Score(10)..=Score(30)), entities[1..=3].to_vec());
+        assert_eq!(index.range(Score(25)..), entities[3..].to_vec());
+        assert!(index.range(Score(30)..Score(30)).is_empty());
+        assert!(index.range(Score(30)..Score(10)).is_empty());
+    }
+
+    #[test]
+    fn for_each_in_range_test() {
+        let mut index = RangeIndex::<Score>::new();
+        let entities: Vec<Entity> = (0..6).map(Entity::new).collect();
+        for (entity, score) in entities.iter().zip([15, 40, 25, 10, 25, 60].iter()) {
+            index.insert(Score(*score), *entity);
+        }
+
+        let mut visited = Vec::new();
+        let mut total = 0;
+        index.for_each_in_range(Score(15)..=Score(40), |score, entity| {
+            visited.push(entity);
+            total += score.0;
+        });
+        assert_eq!(
+            visited,
+            vec![entities[0], entities[2], entities[4], entities[1]]
+        );
+        assert_eq!(total, 105);
+
+        index.for_each_in_range(Score(40)..Score(15), |_, _| {
+            panic!("Inverted ranges are empty")
+        });
+    }
+
+    #[test]
+    fn range_index_update_test() {
+        let mut app_builder = App::build();
+        app_builder.init_range_index::<Score>();
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((Score(3),));
+        let b = world.spawn((Score(8),));
+        app_builder.app.update();
+
+        *app_builder.app.world.get_mut::<Score>(a).unwrap() = Score(12);
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<RangeIndex<Score>>().unwrap();
+        assert_eq!(index.min_key(), Some(&Score(8)));
+        assert_eq!(index.max_key(), Some(&Score(12)));
+        assert_eq!(index.range(Score(0)..Score(10)), vec![b]);
+    }
+
+    // Ord, but deliberately not Hash
+    #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+    struct Version(Vec<u16>);
+
+    #[test]
+    fn ord_index_test() {
+        let mut app_builder = App::build();
+        app_builder.init_ord_index::<Version>();
+        let world = &mut app_builder.app.world;
+        let old = world.spawn((Version(vec![1, 0]),));
+        let new = world.spawn((Version(vec![1, 2, 1]),));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<RangeIndex<Version>>()
+                .unwrap();
+            assert_eq!(index.get(&Version(vec![1, 0])), &[old]);
+            assert_eq!(index.value_of(new), Some(&Version(vec![1, 2, 1])));
+        }
+
+        app_builder.app.world.remove_one::<Version>(old).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<RangeIndex<Version>>()
+            .unwrap();
+        assert!(index.get(&Version(vec![1, 0])).is_empty());
+        assert_eq!(index.value_of(old), None);
+        assert_eq!(index.min_key(), Some(&Version(vec![1, 2, 1])));
+    }
+}
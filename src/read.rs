@@ -0,0 +1,79 @@
+use crate::{ComponentIndex, IndexKey};
+use bevy::prelude::*;
+
+use std::borrow::Cow;
+
+/// The lookups shared by every index variant keyed by `T`,
+/// so that generic code can read from an index without knowing how it's stored
+pub trait IndexRead<T> {
+    /// The entities stored under `key`
+    fn get(&self, key: &T) -> Cow<'_, [Entity]>;
+
+    /// One of the entities stored under `key`, for keys that are expected to be held by a single entity
+    fn get_one(&self, key: &T) -> Option<Entity> {
+        self.get(key).first().copied()
+    }
+
+    fn contains_key(&self, key: &T) -> bool {
+        !self.get(key).is_empty()
+    }
+
+    /// The number of entities in the index
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: IndexKey> IndexRead<T> for ComponentIndex<T> {
+    fn get(&self, key: &T) -> Cow<'_, [Entity]> {
+        ComponentIndex::get(self, key)
+    }
+
+    fn len(&self) -> usize {
+        self.reverse.len()
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::RangeIndex;
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+    struct Score(u32);
+
+    // Written once against the trait, rather than once per index variant
+    fn winner<I: IndexRead<Score>>(index: &I, best: Score) -> Option<Entity> {
+        if index.contains_key(&best) {
+            index.get_one(&best)
+        } else {
+            None
+        }
+    }
+
+    fn check<I: IndexRead<Score>>(index: &I, entities: &[Entity]) {
+        assert_eq!(index.len(), 4);
+        assert!(!index.is_empty());
+        assert_eq!(index.get(&Score(10)).as_ref(), &[entities[0], entities[3]]);
+        assert_eq!(winner(index, Score(30)), Some(entities[1]));
+        assert_eq!(winner(index, Score(40)), None);
+        assert!(!index.contains_key(&Score(40)));
+    }
+
+    #[test]
+    fn index_read_test() {
+        let entities: Vec<Entity> = (0..4).map(Entity::new).collect();
+        let scores = [10, 30, 20, 10];
+        let mut hashed = ComponentIndex::<Score>::new();
+        let mut ordered = RangeIndex::<Score>::new();
+        for (entity, score) in entities.iter().zip(scores.iter()) {
+            hashed.insert(Score(*score), *entity);
+            ordered.insert(Score(*score), *entity);
+        }
+
+        check(&hashed, &entities);
+        check(&ordered, &entities);
+    }
+}
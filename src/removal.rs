@@ -0,0 +1,47 @@
+//! Removed-component detection has moved around between Bevy versions,
+//! so every index goes through `iter_removed` rather than calling into Bevy directly.
+//! Porting to a new Bevy version should only require changing this module.
+
+use bevy::ecs::{QueryFilter, WorldQuery};
+use bevy::prelude::*;
+
+/// Entities that had their `T` removed (or were despawned) since the last frame's trackers were cleared.
+/// `query` can be any query; it is only used to access the world's removal trackers.
+pub(crate) fn iter_removed<'a, T: Component, Q: WorldQuery, F: QueryFilter>(
+    query: &'a Query<'_, Q, F>,
+) -> impl Iterator<Item = Entity> + 'a {
+    query.removed::<T>().iter().copied()
+}
+
+#[allow(dead_code)]
+mod test {
+    use crate::{ComponentIndex, ComponentIndexes};
+    use bevy::prelude::*;
+
+    #[derive(Debug, Clone, Hash, PartialEq, Eq)]
+    struct Marker(u8);
+
+    #[test]
+    fn removal_eviction_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index::<Marker>();
+        let world = &mut app_builder.app.world;
+        let stripped = world.spawn((Marker(0),));
+        let despawned = world.spawn((Marker(0),));
+        let kept = world.spawn((Marker(0),));
+        app_builder.app.update();
+
+        let world = &mut app_builder.app.world;
+        world.remove_one::<Marker>(stripped).unwrap();
+        world.despawn(despawned).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<Marker>>()
+            .unwrap();
+        assert_eq!(index.get(&Marker(0)).as_ref(), &[kept]);
+        assert_eq!(index.value_of(stripped), None);
+        assert_eq!(index.value_of(despawned), None);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::removal::iter_removed;
+use crate::{ComponentIndex, IndexKey, IndexRead};
+use bevy::prelude::*;
+use bevy::tasks::{ComputeTaskPool, TaskPool};
+
+use std::borrow::Cow;
+use std::hash::Hash;
+
+/// An index split into shards by entity, so that the update system can write to every shard in parallel.
+/// Each entity always lives in the same shard, while the entities of a key are spread over all of them,
+/// so reads have to merge the shards back together.
+/// Only worth it for very large indexes that see a lot of changes each frame.
+#[derive(Debug)]
+pub struct ShardedIndex<T: Hash + Eq> {
+    // Nothing begins a frame for the shards, so they don't record per-frame changes that would pile up
+    shards: Vec<ComponentIndex<T>>,
+}
+
+impl<T: Hash + Eq> ShardedIndex<T> {
+    pub fn new(n_shards: usize) -> Self {
+        assert!(n_shards > 0, "ShardedIndex needs at least one shard");
+
+        ShardedIndex::<T> {
+            shards: (0..n_shards).map(|_| ComponentIndex::new()).collect(),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard_of(&self, entity: Entity) -> usize {
+        entity.id() as usize % self.shards.len()
+    }
+
+    /// The entities stored under `key`, gathered shard by shard.
+    /// Entities keep their relative order within a shard, but not across shards.
+    pub fn get(&self, key: &T) -> Vec<Entity> {
+        self.shards
+            .iter()
+            .filter_map(|shard| shard.entities_of(key))
+            .flat_map(|entities| entities.iter().copied())
+            .collect()
+    }
+
+    pub fn value_of(&self, entity: Entity) -> Option<&T> {
+        self.shards[self.shard_of(entity)].value_of(entity)
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) {
+        let shard = self.shard_of(*entity);
+        self.shards[shard].remove(entity);
+    }
+}
+
+impl<T: Hash + Eq> IndexRead<T> for ShardedIndex<T> {
+    fn get(&self, key: &T) -> Cow<'_, [Entity]> {
+        Cow::from(ShardedIndex::get(self, key))
+    }
+
+    fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.reverse.len()).sum()
+    }
+}
+
+impl<T: Hash + Eq + Clone> ShardedIndex<T> {
+    pub(crate) fn insert(&mut self, key: T, entity: Entity) {
+        let shard = self.shard_of(entity);
+        self.shards[shard].insert(key, entity);
+    }
+}
+
+impl<T: Hash + Eq + Clone + Send> ShardedIndex<T> {
+    /// Stores each entity under its new key, or evicts it if the key is `None`.
+    /// The changes are split up by shard, and each shard applies its own on `task_pool`.
+    pub(crate) fn apply_changes(
+        &mut self,
+        task_pool: &TaskPool,
+        changes: Vec<(Entity, Option<T>)>,
+    ) {
+        let mut batches: Vec<Vec<(Entity, Option<T>)>> =
+            (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (entity, key) in changes {
+            batches[self.shard_of(entity)].push((entity, key));
+        }
+
+        let shards = &mut self.shards;
+        task_pool.scope(|scope| {
+            for (shard, batch) in shards.iter_mut().zip(batches) {
+                if batch.is_empty() {
+                    continue;
+                }
+                scope.spawn(async move {
+                    for (entity, key) in batch {
+                        match key {
+                            Some(key) => shard.insert(key, entity),
+                            None => shard.remove(&entity),
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
+
+pub(crate) fn update_sharded_index<T: IndexKey>(
+    mut index: ResMut<ShardedIndex<T>>,
+    task_pool: Res<ComputeTaskPool>,
+    query: Query<(&T, Entity)>,
+    changed_query: Query<(&T, Entity), Changed<T>>,
+) {
+    // Gathering the changes only reads the index, so the writes can all happen in parallel afterwards
+    let mut changes: Vec<(Entity, Option<T>)> = iter_removed::<T, _, _>(&query)
+        .filter(|entity| index.value_of(*entity).is_some())
+        .map(|entity| (entity, None))
+        .collect();
+    changes.extend(
+        changed_query
+            .iter()
+            .filter(|(component, entity)| index.value_of(*entity) != Some(*component))
+            .map(|(component, entity)| (entity, Some(component.clone()))),
+    );
+
+    if !changes.is_empty() {
+        index.apply_changes(&task_pool, changes);
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+
+    fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort();
+        entities
+    }
+
+    #[test]
+    fn sharded_matches_unsharded_test() {
+        let task_pool = TaskPool::new();
+        let mut sharded = ShardedIndex::<u8>::new(4);
+        let mut plain = ComponentIndex::<u8>::new();
+
+        for round in 0..5 {
+            // A deterministic mix of moves and evictions, touching every shard
+            let changes: Vec<(Entity, Option<u8>)> = (0..200)
+                .map(|id| {
+                    let roll = (id * 7 + round * 13) % 11;
+                    let key = if roll == 0 {
+                        None
+                    } else {
+                        Some((roll % 5) as u8)
+                    };
+                    (Entity::new(id), key)
+                })
+                .collect();
+
+            for (entity, key) in changes.iter() {
+                match key {
+                    Some(key) => plain.insert(*key, *entity),
+                    None => plain.remove(entity),
+                }
+            }
+            sharded.apply_changes(&task_pool, changes);
+        }
+
+        for key in 0..5 {
+            assert_eq!(
+                sorted(sharded.get(&key)),
+                sorted(plain.get(&key).to_vec()),
+                "Shards disagree with the unsharded index for key {}",
+                key
+            );
+        }
+        for id in 0..200 {
+            let entity = Entity::new(id);
+            assert_eq!(sharded.value_of(entity), plain.value_of(entity));
+        }
+        for shard in sharded.shards.iter() {
+            assert!(shard.added_this_frame().is_empty());
+            assert!(shard.reassigned_this_frame().is_empty());
+            assert!(shard.evicted_this_frame().is_empty());
+        }
+    }
+}
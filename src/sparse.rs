@@ -0,0 +1,185 @@
+use crate::removal::iter_removed;
+use crate::{IndexKey, IndexRead};
+use bevy::prelude::*;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// An index for keys that are usually held by a single entity, such as unique tiles.
+/// A lone entity is stored inline, and only keys shared by several entities allocate a `Vec`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SparseIndex<T: Hash + Eq> {
+    forward: HashMap<T, Slot>,
+    reverse: HashMap<Entity, T>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Slot {
+    One(Entity),
+    Many(Vec<Entity>),
+}
+
+impl Slot {
+    fn as_slice(&self) -> &[Entity] {
+        match self {
+            Slot::One(entity) => std::slice::from_ref(entity),
+            Slot::Many(entities) => entities,
+        }
+    }
+
+    // Removes `entity`, returning whether the slot is now empty
+    fn remove(&mut self, entity: &Entity) -> bool {
+        match self {
+            Slot::One(e) => e == entity,
+            Slot::Many(entities) => {
+                entities.retain(|e| e != entity);
+                // Shrink back down to inline storage once only one entity is left
+                if let [last] = entities[..] {
+                    *self = Slot::One(last);
+                }
+                false
+            }
+        }
+    }
+}
+
+impl<T: Hash + Eq> SparseIndex<T> {
+    pub fn new() -> Self {
+        SparseIndex::<T>::default()
+    }
+
+    pub fn get(&self, key: &T) -> &[Entity] {
+        match self.forward.get(key) {
+            Some(slot) => slot.as_slice(),
+            None => &[],
+        }
+    }
+
+    pub fn value_of(&self, entity: Entity) -> Option<&T> {
+        self.reverse.get(&entity)
+    }
+
+    /// Whether `key` is held by a single entity, stored without a heap allocation
+    pub fn is_inline(&self, key: &T) -> bool {
+        matches!(self.forward.get(key), Some(Slot::One(_)))
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) {
+        if let Some(old_key) = self.reverse.remove(entity) {
+            let emptied = match self.forward.get_mut(&old_key) {
+                Some(slot) => slot.remove(entity),
+                None => false,
+            };
+
+            if emptied {
+                self.forward.remove(&old_key);
+            }
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone> SparseIndex<T> {
+    pub(crate) fn insert(&mut self, key: T, entity: Entity) {
+        self.remove(&entity);
+
+        match self.forward.get_mut(&key) {
+            Some(slot) => match slot {
+                Slot::One(other) => {
+                    let other = *other;
+                    *slot = Slot::Many(vec![other, entity]);
+                }
+                Slot::Many(entities) => entities.push(entity),
+            },
+            None => {
+                self.forward.insert(key.clone(), Slot::One(entity));
+            }
+        }
+        self.reverse.insert(entity, key);
+    }
+}
+
+impl<T: Hash + Eq> IndexRead<T> for SparseIndex<T> {
+    fn get(&self, key: &T) -> Cow<'_, [Entity]> {
+        Cow::from(SparseIndex::get(self, key))
+    }
+
+    fn len(&self) -> usize {
+        self.reverse.len()
+    }
+}
+
+impl<T: Hash + Eq> Default for SparseIndex<T> {
+    fn default() -> Self {
+        SparseIndex::<T> {
+            forward: HashMap::new(),
+            reverse: HashMap::new(),
+        }
+    }
+}
+
+pub(crate) fn update_sparse_index<T: IndexKey>(
+    mut index: ResMut<SparseIndex<T>>,
+    query: Query<(&T, Entity)>,
+    changed_query: Query<(&T, Entity), Changed<T>>,
+) {
+    for entity in iter_removed::<T, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        if index.value_of(entity) != Some(component) {
+            index.insert(component.clone(), entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    struct Tile(u8, u8);
+
+    #[test]
+    fn promotion_test() {
+        let mut index = SparseIndex::<Tile>::new();
+        let (a, b, c) = (Entity::new(0), Entity::new(1), Entity::new(2));
+
+        index.insert(Tile(0, 0), a);
+        assert!(index.is_inline(&Tile(0, 0)));
+        assert_eq!(index.get(&Tile(0, 0)), &[a]);
+
+        index.insert(Tile(0, 0), b);
+        index.insert(Tile(0, 0), c);
+        assert!(!index.is_inline(&Tile(0, 0)));
+        assert_eq!(index.get(&Tile(0, 0)), &[a, b, c]);
+
+        index.remove(&a);
+        index.insert(Tile(1, 1), b);
+        assert!(index.is_inline(&Tile(0, 0)));
+        assert_eq!(index.get(&Tile(0, 0)), &[c]);
+
+        index.remove(&c);
+        assert!(index.get(&Tile(0, 0)).is_empty());
+        assert!(!index.is_inline(&Tile(0, 0)));
+    }
+
+    #[test]
+    fn sparse_update_test() {
+        let mut app_builder = App::build();
+        app_builder.init_sparse_index::<Tile>();
+        let world = &mut app_builder.app.world;
+        let a = world.spawn((Tile(0, 0),));
+        let b = world.spawn((Tile(0, 1),));
+        app_builder.app.update();
+
+        *app_builder.app.world.get_mut::<Tile>(b).unwrap() = Tile(0, 0);
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<SparseIndex<Tile>>().unwrap();
+        assert_eq!(index.get(&Tile(0, 0)), &[a, b]);
+        assert!(index.get(&Tile(0, 1)).is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::removal::iter_removed;
+use bevy::prelude::*;
+
+use std::collections::HashSet;
+use std::marker::PhantomData;
+
+/// The set of entities that have the marker component `T`, such as a `Selected` tag.
+/// Unlike a `ComponentIndex`, the value of `T` is never looked at, only whether it is present.
+#[derive(Debug)]
+pub struct TagIndex<T> {
+    entities: HashSet<Entity>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> TagIndex<T> {
+    pub fn new() -> Self {
+        TagIndex::<T>::default()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
+
+    /// Every tagged entity, in arbitrary order
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.entities.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+impl<T> Default for TagIndex<T> {
+    fn default() -> Self {
+        TagIndex::<T> {
+            entities: HashSet::new(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+pub(crate) fn update_tag_index<T: Component>(
+    mut index: ResMut<TagIndex<T>>,
+    query: Query<Entity, With<T>>,
+    added_query: Query<Entity, Added<T>>,
+) {
+    for entity in iter_removed::<T, _, _>(&query) {
+        index.entities.remove(&entity);
+    }
+
+    for entity in added_query.iter() {
+        index.entities.insert(entity);
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    struct Selected;
+
+    #[test]
+    fn tag_index_test() {
+        let mut app_builder = App::build();
+        app_builder.init_tag_index::<Selected>();
+        let world = &mut app_builder.app.world;
+        let selected = world.spawn((Selected,));
+        let unselected = world.spawn(());
+        app_builder.app.update();
+
+        {
+            let index = app_builder.resources().get::<TagIndex<Selected>>().unwrap();
+            assert!(index.contains(selected));
+            assert!(!index.contains(unselected));
+            assert_eq!(index.iter().collect::<Vec<Entity>>(), vec![selected]);
+        }
+
+        let world = &mut app_builder.app.world;
+        world.remove_one::<Selected>(selected).unwrap();
+        world.insert_one(unselected, Selected).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder.resources().get::<TagIndex<Selected>>().unwrap();
+        assert!(!index.contains(selected));
+        assert!(index.contains(unselected));
+        assert_eq!(index.len(), 1);
+    }
+}
@@ -0,0 +1,27 @@
+//! Assertions for testing code that relies on indexes.
+//! Enable the `test-utils` feature to use them from other crates.
+
+use crate::{ComponentIndex, IndexKey};
+use bevy::prelude::*;
+
+use std::fmt::Debug;
+
+/// Asserts that the entities stored under `key` in `app`'s `ComponentIndex<T>` are exactly `expected`,
+/// in any order
+pub fn assert_indexed<T: IndexKey + Debug>(app: &App, key: &T, expected: &[Entity]) {
+    let index = app
+        .resources
+        .get::<ComponentIndex<T>>()
+        .expect("No ComponentIndex was initialized for this key type");
+
+    let mut found = index.get(key).to_vec();
+    found.sort();
+    let mut expected = expected.to_vec();
+    expected.sort();
+
+    assert_eq!(
+        found, expected,
+        "Entities indexed under {:?} don't match the expected entities",
+        key
+    );
+}
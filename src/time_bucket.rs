@@ -0,0 +1,193 @@
+use crate::removal::iter_removed;
+use bevy::prelude::*;
+
+use std::collections::{BTreeMap, HashMap};
+
+/// An index over a timestamp derived from the component `C`, such as when an effect was last refreshed,
+/// grouped into windows of `bucket_size` seconds.
+/// Buckets are fixed to absolute time, so entities move from one age to the next as the index's clock advances,
+/// without having to be re-bucketed.
+pub struct TimeBucketIndex<C> {
+    bucket_size: f64,
+    project: fn(&C) -> f64,
+    now: f64,
+    forward: BTreeMap<i64, Vec<Entity>>,
+    reverse: HashMap<Entity, i64>,
+}
+
+impl<C> TimeBucketIndex<C> {
+    pub fn new(bucket_size: f64, project: fn(&C) -> f64) -> Self {
+        assert!(
+            bucket_size > 0.0,
+            "TimeBucketIndex bucket size must be positive"
+        );
+
+        TimeBucketIndex::<C> {
+            bucket_size,
+            project,
+            now: 0.0,
+            forward: BTreeMap::new(),
+            reverse: HashMap::new(),
+        }
+    }
+
+    /// The bucket that `timestamp` falls into
+    pub fn bucket_of(&self, timestamp: f64) -> i64 {
+        (timestamp / self.bucket_size).floor() as i64
+    }
+
+    /// The bucket that `entity` is currently stored under
+    pub fn bucket_of_entity(&self, entity: Entity) -> Option<i64> {
+        self.reverse.get(&entity).copied()
+    }
+
+    /// The time of the index's clock, in seconds since startup unless advanced by hand
+    pub fn now(&self) -> f64 {
+        self.now
+    }
+
+    /// Moves the index's clock forward by `seconds`
+    pub fn advance(&mut self, seconds: f64) {
+        self.now += seconds;
+    }
+
+    /// All entities whose timestamp falls into `bucket`
+    pub fn get_bucket(&self, bucket: i64) -> &[Entity] {
+        match self.forward.get(&bucket) {
+            Some(e) => e,
+            None => &[],
+        }
+    }
+
+    /// All entities whose timestamp falls into the bucket `age` buckets before the current one,
+    /// so an `age` of 0 is the window that is still in progress
+    pub fn get_age(&self, age: u32) -> &[Entity] {
+        self.get_bucket(self.bucket_of(self.now) - age as i64)
+    }
+
+    /// All entities whose timestamp falls into the current bucket or the `n_buckets - 1` before it,
+    /// from oldest to newest
+    pub fn get_recent(&self, n_buckets: u32) -> Vec<Entity> {
+        if n_buckets == 0 {
+            return Vec::new();
+        }
+
+        let current = self.bucket_of(self.now);
+        self.forward
+            .range(current - n_buckets as i64 + 1..=current)
+            .flat_map(|(_, entities)| entities.iter().copied())
+            .collect()
+    }
+
+    pub(crate) fn insert(&mut self, component: &C, entity: Entity) {
+        self.remove(&entity);
+
+        let bucket = self.bucket_of((self.project)(component));
+        self.forward
+            .entry(bucket)
+            .or_insert_with(Vec::new)
+            .push(entity);
+        self.reverse.insert(entity, bucket);
+    }
+
+    pub(crate) fn remove(&mut self, entity: &Entity) {
+        if let Some(old_bucket) = self.reverse.remove(entity) {
+            if let Some(entities) = self.forward.get_mut(&old_bucket) {
+                entities.retain(|e| e != entity);
+                if entities.is_empty() {
+                    self.forward.remove(&old_bucket);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn advance_time_bucket_clock<C: Component>(
+    time: Res<Time>,
+    mut index: ResMut<TimeBucketIndex<C>>,
+) {
+    index.advance(time.delta_seconds_f64());
+}
+
+pub(crate) fn update_time_bucket_index<C: Component>(
+    mut index: ResMut<TimeBucketIndex<C>>,
+    query: Query<(&C, Entity)>,
+    changed_query: Query<(&C, Entity), Changed<C>>,
+) {
+    for entity in iter_removed::<C, _, _>(&query) {
+        index.remove(&entity);
+    }
+
+    for (component, entity) in changed_query.iter() {
+        index.insert(component, entity);
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    struct LastHit(f64);
+
+    fn last_hit(hit: &LastHit) -> f64 {
+        hit.0
+    }
+
+    #[test]
+    fn aging_test() {
+        let mut index = TimeBucketIndex::new(0.1, last_hit);
+        let (early, late) = (Entity::new(0), Entity::new(1));
+        index.insert(&LastHit(0.02), early);
+        index.insert(&LastHit(0.15), late);
+        index.advance(0.18);
+
+        assert_eq!(index.get_age(0), &[late]);
+        assert_eq!(index.get_age(1), &[early]);
+        assert_eq!(index.get_recent(2), vec![early, late]);
+
+        // Time passing shifts both entities back a window without touching them
+        index.advance(0.1);
+        assert!(index.get_age(0).is_empty());
+        assert_eq!(index.get_age(1), &[late]);
+        assert_eq!(index.get_age(2), &[early]);
+        assert_eq!(index.get_recent(2), vec![late]);
+        assert!(index.get_recent(0).is_empty());
+    }
+
+    #[test]
+    fn time_bucket_update_test() {
+        let mut app_builder = App::build();
+        // Time never advances on its own here, so the clock is driven by hand
+        app_builder
+            .add_resource(Time::default())
+            .init_time_bucket_index::<LastHit>(1.0, last_hit);
+        let entity = app_builder.app.world.spawn((LastHit(0.5),));
+        app_builder.app.update();
+
+        app_builder
+            .resources()
+            .get_mut::<TimeBucketIndex<LastHit>>()
+            .unwrap()
+            .advance(2.0);
+        {
+            let index = app_builder
+                .resources()
+                .get::<TimeBucketIndex<LastHit>>()
+                .unwrap();
+            assert_eq!(index.get_age(2), &[entity]);
+        }
+
+        // Refreshing the timestamp brings the entity back into the current window
+        app_builder.app.world.get_mut::<LastHit>(entity).unwrap().0 = 2.3;
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<TimeBucketIndex<LastHit>>()
+            .unwrap();
+        assert_eq!(index.get_age(0), &[entity]);
+        assert_eq!(index.bucket_of_entity(entity), Some(2));
+        assert!(index.get_bucket(0).is_empty());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::removal::iter_removed;
+use crate::{ComponentIndex, IndexKey};
+use bevy::prelude::*;
+
+use std::hash::Hash;
+
+impl<A: Hash + Eq, B: Hash + Eq> ComponentIndex<(A, B)> {
+    /// All entities whose key matches the given fields, with `None` matching any value.
+    /// This walks every key in the index.
+    pub fn get_partial(&self, a: Option<&A>, b: Option<&B>) -> Vec<Entity> {
+        self.forward
+            .iter_all()
+            .filter(|((key_a, key_b), _)| {
+                a.map_or(true, |a| a == key_a) && b.map_or(true, |b| b == key_b)
+            })
+            .flat_map(|(_, entities)| entities.iter().copied())
+            .collect()
+    }
+}
+
+pub(crate) fn update_tuple_index<A: IndexKey, B: IndexKey>(
+    mut index: ResMut<ComponentIndex<(A, B)>>,
+    query: Query<(&A, &B, Entity)>,
+    changed_query: Query<(&A, &B, Entity), Or<(Changed<A>, Changed<B>)>>,
+) {
+    // Losing either half of the key evicts the entity
+    for entity in iter_removed::<A, _, _>(&query).chain(iter_removed::<B, _, _>(&query)) {
+        index.remove(&entity);
+    }
+
+    for (a, b, entity) in changed_query.iter() {
+        let key = (a.clone(), b.clone());
+        if !index.is_stored_as(entity, &key) {
+            index.insert(key, entity);
+        }
+    }
+}
+
+#[allow(dead_code)]
+mod test {
+    use super::*;
+    use crate::ComponentIndexes;
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    enum Faction {
+        Red,
+        Blue,
+    }
+
+    #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+    enum Class {
+        Archer,
+        Knight,
+    }
+
+    #[test]
+    fn partial_match_test() {
+        let mut index = ComponentIndex::<(Faction, Class)>::new();
+        let red_archer = Entity::new(0);
+        let red_knight = Entity::new(1);
+        let blue_knight = Entity::new(2);
+        index.insert((Faction::Red, Class::Archer), red_archer);
+        index.insert((Faction::Red, Class::Knight), red_knight);
+        index.insert((Faction::Blue, Class::Knight), blue_knight);
+
+        let mut reds = index.get_partial(Some(&Faction::Red), None);
+        reds.sort();
+        assert_eq!(reds, vec![red_archer, red_knight]);
+
+        let mut knights = index.get_partial(None, Some(&Class::Knight));
+        knights.sort();
+        assert_eq!(knights, vec![red_knight, blue_knight]);
+
+        assert_eq!(
+            index.get_partial(Some(&Faction::Blue), Some(&Class::Knight)),
+            vec![blue_knight]
+        );
+        assert!(index
+            .get_partial(Some(&Faction::Blue), Some(&Class::Archer))
+            .is_empty());
+        assert_eq!(index.get_partial(None, None).len(), 3);
+    }
+
+    #[test]
+    fn tuple_index_test() {
+        let mut app_builder = App::build();
+        app_builder.init_index_tuple::<Faction, Class>();
+        let world = &mut app_builder.app.world;
+        let red_archer = world.spawn((Faction::Red, Class::Archer));
+        let red_knight = world.spawn((Faction::Red, Class::Knight));
+        let lone_blue = world.spawn((Faction::Blue,));
+        app_builder.app.update();
+
+        {
+            let index = app_builder
+                .resources()
+                .get::<ComponentIndex<(Faction, Class)>>()
+                .unwrap();
+            assert_eq!(
+                index.get(&(Faction::Red, Class::Archer)).as_ref(),
+                &[red_archer]
+            );
+            assert_eq!(
+                index.get(&(Faction::Red, Class::Knight)).as_ref(),
+                &[red_knight]
+            );
+            assert_eq!(index.value_of(lone_blue), None);
+        }
+
+        let world = &mut app_builder.app.world;
+        world.remove_one::<Class>(red_archer).unwrap();
+        *world.get_mut::<Faction>(red_knight).unwrap() = Faction::Blue;
+        world.insert_one(lone_blue, Class::Archer).unwrap();
+        app_builder.app.update();
+
+        let index = app_builder
+            .resources()
+            .get::<ComponentIndex<(Faction, Class)>>()
+            .unwrap();
+        assert_eq!(index.value_of(red_archer), None);
+        assert!(index.get(&(Faction::Red, Class::Knight)).is_empty());
+        assert_eq!(
+            index.get(&(Faction::Blue, Class::Knight)).as_ref(),
+            &[red_knight]
+        );
+        assert_eq!(
+            index.get(&(Faction::Blue, Class::Archer)).as_ref(),
+            &[lone_blue]
+        );
+    }
+}
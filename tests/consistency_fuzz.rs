@@ -0,0 +1,119 @@
+//! Applies random sequences of operations to a `ComponentIndex`,
+//! checking after every step that it agrees with a plain `HashMap` model of the same operations.
+
+use bevy::prelude::*;
+use bevy_index::{ComponentIndex, ConsistencyPolicy};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::collections::HashMap;
+
+const N_ENTITIES: u32 = 40;
+const N_KEYS: u8 = 8;
+const N_STEPS: usize = 500;
+
+fn random_entity(rng: &mut StdRng) -> Entity {
+    Entity::new(rng.gen_range(0, N_ENTITIES))
+}
+
+fn random_key(rng: &mut StdRng) -> u8 {
+    rng.gen_range(0, N_KEYS)
+}
+
+// Applies one random operation to both the index and the model
+fn step(rng: &mut StdRng, index: &mut ComponentIndex<u8>, model: &mut HashMap<Entity, u8>) {
+    match rng.gen_range(0, 6) {
+        // Plain inserts are the most common, and reassign entities that are already indexed
+        0 | 1 => {
+            let (entity, key) = (random_entity(rng), random_key(rng));
+            index.insert(key, entity);
+            model.insert(entity, key);
+        }
+        2 => {
+            let entity = random_entity(rng);
+            let was_indexed = model.remove(&entity).is_some();
+            assert_eq!(index.remove_many(Some(entity)), was_indexed as usize);
+        }
+        3 => {
+            let entities: Vec<Entity> = (0..rng.gen_range(0, 5))
+                .map(|_| random_entity(rng))
+                .collect();
+            let n_indexed = entities
+                .iter()
+                .filter(|entity| model.remove(*entity).is_some())
+                .count();
+            assert_eq!(index.remove_many(entities), n_indexed);
+        }
+        4 => {
+            let key = random_key(rng);
+            let entities: Vec<Entity> = (0..rng.gen_range(0, 5))
+                .map(|_| random_entity(rng))
+                .collect();
+            index.insert_batch(key, &entities);
+            for entity in entities {
+                model.insert(entity, key);
+            }
+        }
+        _ => {
+            let dropped_key = random_key(rng);
+            index.retain_entities(|_, key| *key != dropped_key);
+            model.retain(|_, key| *key != dropped_key);
+        }
+    }
+}
+
+fn assert_agrees(index: &ComponentIndex<u8>, model: &HashMap<Entity, u8>) {
+    index.debug_assert_consistent();
+
+    for id in 0..N_ENTITIES {
+        let entity = Entity::new(id);
+        assert_eq!(index.value_of(entity), model.get(&entity));
+    }
+
+    for key in 0..N_KEYS {
+        let mut found = index.get(&key).to_vec();
+        found.sort();
+        let mut expected: Vec<Entity> = model
+            .iter()
+            .filter(|(_, k)| **k == key)
+            .map(|(entity, _)| *entity)
+            .collect();
+        expected.sort();
+
+        assert_eq!(found, expected, "Entities under key {} disagree", key);
+        assert_eq!(index.entities_len(&key), expected.len());
+    }
+}
+
+fn run(seed: u64, drop_empty_keys: bool) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut index = ComponentIndex::<u8>::new();
+    index.set_consistency_policy(ConsistencyPolicy::Panic);
+    index.set_drop_empty_keys(drop_empty_keys);
+    let mut model = HashMap::new();
+
+    for _ in 0..N_STEPS {
+        step(&mut rng, &mut index, &mut model);
+        assert_agrees(&index, &model);
+
+        // Compacting the index must never change what it stores
+        if rng.gen_bool(0.02) {
+            index.clean();
+            assert_agrees(&index, &model);
+        }
+    }
+}
+
+#[test]
+fn random_operations_stay_consistent() {
+    for seed in 0..20 {
+        run(seed, false);
+    }
+}
+
+#[test]
+fn random_operations_stay_consistent_when_dropping_empty_keys() {
+    for seed in 0..20 {
+        run(seed, true);
+    }
+}
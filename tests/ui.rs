@@ -0,0 +1,7 @@
+// The expected errors rely on `#[diagnostic::on_unimplemented]`, which is only applied from Rust 1.78
+#[rustversion::attr(not(since(1.78)), ignore)]
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
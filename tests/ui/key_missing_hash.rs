@@ -0,0 +1,15 @@
+use bevy_index::IndexKey;
+
+// Forgot to derive Hash
+#[derive(Clone, PartialEq, Eq)]
+struct Position {
+    x: i32,
+    y: i32,
+}
+
+// Has the same bound as `ComponentIndexes::init_index`, without tying this test to where that is declared
+fn index_by<T: IndexKey>() {}
+
+fn main() {
+    index_by::<Position>();
+}